@@ -0,0 +1,250 @@
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+/// What a `BackgroundWriter` does with records written while its queue is full
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OverflowPolicy {
+    /// Wait for the background thread to make room. This is the default
+    #[default]
+    Block,
+    /// Discard the record, counting it in `BackgroundHandle::dropped`
+    Drop,
+}
+
+enum Message {
+    Record(Vec<u8>),
+    Flush(SyncSender<io::Result<()>>),
+    Shutdown(SyncSender<io::Result<()>>),
+}
+
+/// A writer which hands formatted records to a bounded queue drained by a background
+/// thread, keeping I/O off of the threads that log
+///
+/// ```rust
+/// use json_env_logger::{BackgroundWriter, OverflowPolicy};
+///
+/// let (writer, handle) = BackgroundWriter::new(std::io::stdout(), 1024);
+/// json_env_logger::builder()
+///     .background(writer.overflow(OverflowPolicy::Drop))
+///     .init();
+/// log::info!("👋");
+/// // before exiting
+/// handle.shutdown().unwrap();
+/// ```
+pub struct BackgroundWriter {
+    sender: SyncSender<Message>,
+    overflow: OverflowPolicy,
+    dropped: Arc<AtomicU64>,
+}
+
+/// Flushes or shuts down the background thread of a `BackgroundWriter`
+pub struct BackgroundHandle {
+    sender: SyncSender<Message>,
+    dropped: Arc<AtomicU64>,
+    thread: JoinHandle<()>,
+}
+
+impl BackgroundWriter {
+    /// Spawns a thread writing records to the provided writer, queuing up to
+    /// `capacity` records for it
+    pub fn new<W>(
+        writer: W,
+        capacity: usize,
+    ) -> (Self, BackgroundHandle)
+    where
+        W: Write + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let thread = thread::Builder::new()
+            .name("json_env_logger".into())
+            .spawn(move || drain(writer, receiver))
+            .expect("failed to spawn background writer thread");
+        let handle = BackgroundHandle {
+            sender: sender.clone(),
+            dropped: dropped.clone(),
+            thread,
+        };
+        (
+            BackgroundWriter {
+                sender,
+                overflow: OverflowPolicy::default(),
+                dropped,
+            },
+            handle,
+        )
+    }
+
+    /// Sets what happens to records written while the queue is full.
+    /// Defaults to `OverflowPolicy::Block`
+    pub fn overflow(
+        mut self,
+        policy: OverflowPolicy,
+    ) -> Self {
+        self.overflow = policy;
+        self
+    }
+}
+
+impl Write for BackgroundWriter {
+    fn write(
+        &mut self,
+        buf: &[u8],
+    ) -> io::Result<usize> {
+        let record = Message::Record(buf.to_vec());
+        match self.overflow {
+            OverflowPolicy::Block => self.sender.send(record).map_err(|_| shut_down())?,
+            OverflowPolicy::Drop => match self.sender.try_send(record) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(TrySendError::Disconnected(_)) => return Err(shut_down()),
+            },
+        }
+        Ok(buf.len())
+    }
+
+    /// Waits for queued records to be written and flushed
+    fn flush(&mut self) -> io::Result<()> {
+        request(&self.sender, Message::Flush)
+    }
+}
+
+impl BackgroundHandle {
+    /// Waits for queued records to be written and flushed
+    pub fn flush(&self) -> io::Result<()> {
+        request(&self.sender, Message::Flush)
+    }
+
+    /// Yields the number of records discarded because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Waits for queued records to be written and flushed, then stops the background
+    /// thread. Records written afterwards fail with `io::ErrorKind::BrokenPipe`
+    pub fn shutdown(self) -> io::Result<()> {
+        let flushed = request(&self.sender, Message::Shutdown);
+        let _ = self.thread.join();
+        flushed
+    }
+}
+
+/// Sends a message to the background thread and waits for its reply
+fn request(
+    sender: &SyncSender<Message>,
+    message: fn(SyncSender<io::Result<()>>) -> Message,
+) -> io::Result<()> {
+    let (reply, replied) = mpsc::sync_channel(1);
+    sender.send(message(reply)).map_err(|_| shut_down())?;
+    replied.recv().map_err(|_| shut_down())?
+}
+
+fn drain<W: Write>(
+    mut writer: W,
+    receiver: Receiver<Message>,
+) {
+    for message in receiver {
+        match message {
+            // there's nobody to report errors writing individual records to
+            Message::Record(record) => {
+                let _ = writer.write_all(&record);
+            }
+            Message::Flush(reply) => {
+                let _ = reply.send(writer.flush());
+            }
+            Message::Shutdown(reply) => {
+                let _ = reply.send(writer.flush());
+                return;
+            }
+        }
+    }
+    let _ = writer.flush();
+}
+
+fn shut_down() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "background writer has shut down")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{error::Error, sync::Mutex};
+
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(
+            &mut self,
+            buf: &[u8],
+        ) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn drops_records_when_full() -> Result<(), Box<dyn Error>> {
+        /// signals when a write starts, then waits until the gate opens
+        struct Gated {
+            sink: Sink,
+            started: SyncSender<()>,
+            gate: Receiver<()>,
+        }
+
+        impl Write for Gated {
+            fn write(
+                &mut self,
+                buf: &[u8],
+            ) -> io::Result<usize> {
+                let _ = self.started.send(());
+                let _ = self.gate.recv();
+                self.sink.write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let sink = Sink::default();
+        let (started, writing) = mpsc::sync_channel(2);
+        let (open, gate) = mpsc::sync_channel(0);
+        let (writer, handle) = BackgroundWriter::new(
+            Gated {
+                sink: sink.clone(),
+                started,
+                gate,
+            },
+            1,
+        );
+        let mut writer = writer.overflow(OverflowPolicy::Drop);
+        writer.write_all(b"1\n")?;
+        writing.recv()?;
+        for record in [b"2\n", b"3\n", b"4\n"] {
+            writer.write_all(record)?;
+        }
+        drop(open);
+        handle.flush()?;
+        assert_eq!(handle.dropped(), 2);
+        handle.shutdown()?;
+        assert_eq!(&*sink.0.lock().unwrap(), b"1\n2\n");
+        assert_eq!(
+            writer.write_all(b"5\n").map_err(|err| err.kind()),
+            Err(io::ErrorKind::BrokenPipe)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+//! Fields written with every record logged by the current thread, i.e. a request id
+//!
+//! ```rust
+//! json_env_logger::init();
+//! json_env_logger::context::set("request_id", "0b4fc3a2-7d1e-4b8a-9f6e-2c5d8a1e3f70");
+//! log::info!("handling request");
+//! log::info!("handled request");
+//! json_env_logger::context::clear();
+//! ```
+//!
+//! Context fields are written as kv fields, ahead of a record's own, so they're renamed, redacted
+//! and nested like any other.
+//! Note, threads don't inherit the context of the thread that spawned them
+
+use std::cell::RefCell;
+
+thread_local! {
+    static FIELDS: RefCell<Vec<(String, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Sets a field written with every record logged by the current thread,
+/// replacing any previously set value for the same key
+pub fn set(
+    key: impl Into<String>,
+    value: impl Into<String>,
+) {
+    let (key, value) = (key.into(), value.into());
+    FIELDS.with(|fields| {
+        let mut fields = fields.borrow_mut();
+        match fields.iter_mut().find(|(existing, _)| *existing == key) {
+            Some((_, existing)) => *existing = value,
+            None => fields.push((key, value)),
+        }
+    })
+}
+
+/// Removes a field set for the current thread
+pub fn remove(key: &str) {
+    FIELDS.with(|fields| fields.borrow_mut().retain(|(existing, _)| existing != key))
+}
+
+/// Removes all fields set for the current thread
+pub fn clear() {
+    FIELDS.with(|fields| fields.borrow_mut().clear())
+}
+
+/// Yields the result of applying a function to the fields set for the current thread
+pub(crate) fn with_fields<R>(f: impl FnOnce(&[(String, String)]) -> R) -> R {
+    FIELDS.with(|fields| f(&fields.borrow()))
+}
@@ -7,7 +7,8 @@
 //!
 //! * `iso-timestamps`
 //!
-//! By default, a timestamp field called `ts` is emitted with the current unix epic timestamp in seconds
+//! By default, a timestamp field called `ts` is emitted with the current unix epoch timestamp in milliseconds.
+//! Use `JsonEnvLogger::timestamp_unit` with `TimestampUnit::Seconds` for whole seconds instead.
 //! You can replace this with IOS-8601 timestamps by enabling the `iso-timestamps` feature. Note, this will add `chrono` crate
 //! to your dependency tree.
 //!
@@ -18,23 +19,121 @@
 //! * `backtrace`
 //!
 //! When registering a panic hook with `panic_hook` by default backtraces are omitted. You can
-//! annotate your error with then by enabling the `backtrace` feature.
+//! annotate your error with then by enabling the `backtrace` feature. Use `panic_hook_with_options`
+//! to skip resolving symbols or, with the `structured-values` feature, to write backtraces as arrays of frames.
 //!
 //! ```toml
 //! [dependencies]
 //! json_env_logger = { version = "0.1", features = ["backtrace"] }
 //! ```
+//! * `structured-values`
+//!
+//! By default, kv values that aren't strings, numbers, bools or null are serialized as JSON strings
+//! of their `Display` impl. You can serialize maps, sequences and other `serde::Serialize` values as
+//! nested JSON by enabling the `structured-values` feature.
+//!
+//! ```toml
+//! [dependencies]
+//! json_env_logger = { version = "0.1", features = ["structured-values"] }
+//! ```
+//! * `bunyan`
+//!
+//! Enables `JsonEnvLogger::bunyan` which writes records in the [Bunyan](https://github.com/trentm/node-bunyan)
+//! format. Note, this will add the `chrono` and `hostname` crates to your dependency tree.
+//!
+//! ```toml
+//! [dependencies]
+//! json_env_logger = { version = "0.1", features = ["bunyan"] }
+//! ```
+//! * `base64`
+//!
+//! Enables `JsonEnvLogger::bytes_as_base64` which writes byte slice kv values with the provided keys
+//! as base64 encoded strings.
+//!
+//! ```toml
+//! [dependencies]
+//! json_env_logger = { version = "0.1", features = ["base64"] }
+//! ```
+//! * `hostname`
+//!
+//! Enables `JsonEnvLogger::with_hostname` which writes the name of the current machine. Note, this
+//! will add the `hostname` crate to your dependency tree.
+//!
+//! ```toml
+//! [dependencies]
+//! json_env_logger = { version = "0.1", features = ["hostname"] }
+//! ```
+//! * `tracing-span-id`
+//!
+//! Enables `JsonEnvLogger::with_span_id` which writes the id of the current `tracing` span.
+//! Note, this will add the `tracing` crate to your dependency tree.
+//!
+//! ```toml
+//! [dependencies]
+//! json_env_logger = { version = "0.1", features = ["tracing-span-id"] }
+//! ```
+//! * `rotating-file`
+//!
+//! Enables `RotatingFileTarget` which writes records to a file that's rolled over by size or age.
+//! Enabling the `gzip` feature as well compresses rolled over files. Note, `gzip` will add the
+//! `miniz_oxide` crate to your dependency tree.
+//!
+//! ```toml
+//! [dependencies]
+//! json_env_logger = { version = "0.1", features = ["rotating-file", "gzip"] }
+//! ```
+//! * `serde_json`
+//!
+//! Enabled by default. Strings and floats are encoded with `serde_json`, which `JsonEnvLogger::inject_fields`
+//! and the `structured-values` feature also depend on. Disable default features for a leaner build
+//! which encodes them by hand, with identical output, and removes `serde_json` from your dependency tree.
+//!
+//! ```toml
+//! [dependencies]
+//! json_env_logger = { version = "0.1", default-features = false, features = ["kv"] }
+//! ```
+//! * `kv`
+//!
+//! Enabled by default. Writes the kv fields of records, along with the fields of panics logged by
+//! `panic_hook`. Disable default features for a minimal build which writes only the fields of
+//! records you've configured, i.e. `level`, `ts` and `msg`, and doesn't enable `log`'s kv support.
+//! Options which only apply to kv fields, i.e. `JsonEnvLogger::redact`, have no effect without it.
+//!
+//! ```toml
+//! [dependencies]
+//! json_env_logger = { version = "0.1", default-features = false }
+//! ```
+
+mod background;
+pub mod context;
+#[cfg(feature = "rotating-file")]
+mod rotating;
+pub use background::{BackgroundHandle, BackgroundWriter, OverflowPolicy};
+#[cfg(feature = "rotating-file")]
+pub use rotating::RotatingFileTarget;
 
 // export to make types accessible without
 // requiring adding another Cargo.toml dependency
 #[doc(hidden)]
 pub extern crate env_logger;
 
-use env_logger::Builder;
+use env_logger::{filter::Filter, Builder};
+#[cfg(feature = "kv")]
 use log::kv;
+#[cfg(feature = "kv")]
+use log::kv::VisitSource;
 use std::{
+    borrow::Cow,
+    cell::RefCell,
+    fmt,
     io::{self, Write},
-    panic, thread,
+    panic, process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    thread,
+    time::{Instant, SystemTime},
 };
 
 /// Register configured json env logger implementation with `log` crate.
@@ -57,157 +156,6830 @@ pub fn try_init() -> Result<(), log::SetLoggerError> {
     builder().try_init()
 }
 
+/// Register configured json env logger implementation with `log` crate,
+/// reading filters from the provided env variable rather than `RUST_LOG`
+///
+/// # panics
+///
+/// Panics if logger has already been configured
+pub fn init_from_env(env_var: &str) {
+    try_init_from_env(env_var).unwrap()
+}
+
+/// Register configured json env logger with `log` crate,
+/// reading filters from the provided env variable rather than `RUST_LOG`
+///
+/// Will yield an `log::SetLoggerError` when a logger has already
+/// been configured
+pub fn try_init_from_env(env_var: &str) -> Result<(), log::SetLoggerError> {
+    builder_from_env(env_var).try_init()
+}
+
 /// Register a panic hook that serializes panic information as json
 /// and logs via `log::error`
+///
+/// Panics on spawned threads are logged with the name of the panicking thread.
+/// This replaces any previously registered panic hook. See `panic_hook_chained`
+/// to preserve it
 pub fn panic_hook() {
-    panic::set_hook(Box::new(|info| {
-        let thread = thread::current();
-        let thread = thread.name().unwrap_or("unnamed");
-
-        let msg = match info.payload().downcast_ref::<&'static str>() {
-            Some(s) => *s,
-            None => match info.payload().downcast_ref::<String>() {
-                Some(s) => &**s,
-                None => "Box<Any>",
-            },
+    panic_hook_with_options(PanicHookOptions::default())
+}
+
+/// Register a panic hook that logs panics via `log::error` with the provided target
+///
+/// Unlike `panic_hook`, the panic message is written as the record's message as is,
+/// rather than interpolated into `panicked at '{}'`, alongside the `thread` and
+/// `location` fields. This replaces any previously registered panic hook
+pub fn panic_hook_with(target: impl Into<String>) {
+    panic_hook_with_options(PanicHookOptions::new().target(target))
+}
+
+/// Register a panic hook that logs panics via `log::error` as configured by the provided options
+///
+/// ```rust
+/// use json_env_logger::PanicHookOptions;
+///
+/// json_env_logger::panic_hook_with_options(PanicHookOptions::new().target("panics"));
+/// ```
+///
+/// This replaces any previously registered panic hook
+pub fn panic_hook_with_options(options: PanicHookOptions) {
+    panic::set_hook(Box::new(move |info| {
+        log_panic(log::logger(), info, &options)
+    }));
+}
+
+/// Options for the panic hook registered with `panic_hook_with_options`
+#[derive(Clone, Debug)]
+pub struct PanicHookOptions {
+    target: Option<String>,
+    #[cfg(feature = "backtrace")]
+    resolve_symbols: bool,
+    #[cfg(all(feature = "backtrace", feature = "structured-values"))]
+    structured_backtrace: bool,
+}
+
+impl PanicHookOptions {
+    /// Creates the options `panic_hook` uses
+    pub fn new() -> Self {
+        PanicHookOptions {
+            target: None,
+            #[cfg(feature = "backtrace")]
+            resolve_symbols: true,
+            #[cfg(all(feature = "backtrace", feature = "structured-values"))]
+            structured_backtrace: false,
+        }
+    }
+
+    /// Logs panics with the provided target, writing the panic message as the record's
+    /// message as is, as `panic_hook_with` does
+    pub fn target(
+        mut self,
+        target: impl Into<String>,
+    ) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Enables or disables resolving the symbol names, files and lines of backtrace frames.
+    /// Unresolved backtraces are cheaper to capture but only include instruction
+    /// addresses. Defaults to `true`
+    #[cfg(feature = "backtrace")]
+    pub fn resolve_symbols(
+        mut self,
+        resolve: bool,
+    ) -> Self {
+        self.resolve_symbols = resolve;
+        self
+    }
+
+    /// Enables or disables writing backtraces as an array of frame objects with
+    /// `symbol`, `file` and `line` fields, or `ip` when unresolved, rather than
+    /// a single `Debug` formatted string. Defaults to `false`
+    #[cfg(all(feature = "backtrace", feature = "structured-values"))]
+    pub fn structured_backtrace(
+        mut self,
+        structured: bool,
+    ) -> Self {
+        self.structured_backtrace = structured;
+        self
+    }
+}
+
+impl Default for PanicHookOptions {
+    fn default() -> Self {
+        PanicHookOptions::new()
+    }
+}
+
+/// Register a panic hook that serializes panic information as json
+/// and logs via `log::error` before invoking the previously registered panic hook,
+/// i.e. a crash reporter or the default hook which prints to stderr
+pub fn panic_hook_chained() {
+    chain_panic_hook(log::logger)
+}
+
+fn chain_panic_hook(logger: fn() -> &'static dyn log::Log) {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        log_panic(logger(), info, &PanicHookOptions::default());
+        previous(info);
+    }));
+}
+
+/// Logs a panic with the configured target, if any, otherwise with this crate's target
+/// and the panic message interpolated into `panicked at '{}'`
+fn log_panic(
+    logger: &dyn log::Log,
+    info: &panic::PanicHookInfo,
+    options: &PanicHookOptions,
+) {
+    let target = options.target.as_deref();
+    let metadata = log::Metadata::builder()
+        .level(log::Level::Error)
+        .target(target.unwrap_or(module_path!()))
+        .build();
+    if !logger.enabled(&metadata) {
+        return;
+    }
+    let msg = match info.payload().downcast_ref::<&'static str>() {
+        Some(s) => *s,
+        None => match info.payload().downcast_ref::<String>() {
+            Some(s) => &**s,
+            None => "Box<Any>",
+        },
+    };
+
+    // without kv support, panics are logged with their message alone
+    #[cfg(feature = "kv")]
+    let thread = thread::current();
+    #[cfg(feature = "kv")]
+    let location = info.location().map(|location| {
+        (
+            format!(
+                "{}:{}:{}",
+                location.file(),
+                location.line(),
+                location.column()
+            ),
+            location.line(),
+            location.column(),
+        )
+    });
+    #[cfg(feature = "backtrace")]
+    let backtrace = PanicBacktrace::capture(options);
+
+    #[cfg(feature = "kv")]
+    let mut kvs = vec![(
+        "thread",
+        kv::Value::from(thread.name().unwrap_or("unnamed")),
+    )];
+    #[cfg(feature = "kv")]
+    if let Some((location, line, column)) = &location {
+        kvs.push(("location", kv::Value::from(location.as_str())));
+        kvs.push(("line", kv::Value::from(*line)));
+        kvs.push(("column", kv::Value::from(*column)));
+    }
+    #[cfg(feature = "backtrace")]
+    kvs.push(("backtrace", backtrace.to_value()));
+
+    let mut record = log::Record::builder();
+    record
+        .level(log::Level::Error)
+        .target(module_path!())
+        .module_path_static(Some(module_path!()))
+        .file_static(Some(file!()))
+        .line(Some(line!()));
+    #[cfg(feature = "kv")]
+    record.key_values(&kvs);
+    match target {
+        Some(target) => logger.log(&record.target(target).args(format_args!("{}", msg)).build()),
+        None => logger.log(&record.args(format_args!("panicked at '{}'", msg)).build()),
+    }
+}
+
+/// A backtrace captured by the panic hook
+#[cfg(feature = "backtrace")]
+enum PanicBacktrace {
+    Debug(String),
+    #[cfg(feature = "structured-values")]
+    Frames(serde_json::Value),
+}
+
+#[cfg(feature = "backtrace")]
+impl PanicBacktrace {
+    fn capture(options: &PanicHookOptions) -> Self {
+        let backtrace = if options.resolve_symbols {
+            backtrace::Backtrace::new()
+        } else {
+            backtrace::Backtrace::new_unresolved()
         };
+        #[cfg(feature = "structured-values")]
+        {
+            if options.structured_backtrace {
+                return PanicBacktrace::Frames(backtrace_frames(&backtrace));
+            }
+        }
+        PanicBacktrace::Debug(format!("{:?}", backtrace))
+    }
 
-        match info.location() {
-            Some(location) => {
-                #[cfg(not(feature = "backtrace"))]
-                {
-                    kv_log_macro::error!(
-                        "panicked at '{}'", msg,
-                        {
-                            thread: thread,
-                            location: format!("{}:{}", location.file(), location.line())
-                        }
-                    );
-                }
+    fn to_value(&self) -> kv::Value<'_> {
+        match self {
+            PanicBacktrace::Debug(backtrace) => kv::Value::from(backtrace.as_str()),
+            #[cfg(feature = "structured-values")]
+            PanicBacktrace::Frames(frames) => kv::Value::from_serde(frames),
+        }
+    }
+}
 
-                #[cfg(feature = "backtrace")]
-                {
-                    kv_log_macro::error!(
-                        "panicked at '{}'", msg,
-                        {
-                            thread: thread,
-                            location: format!("{}:{}", location.file(), location.line()),
-                            backtrace: format!("{:?}", backtrace::Backtrace::new())
-                        }
-                    );
-                }
+/// Yields an array with an object for each symbol of each frame, or each frame's
+/// instruction pointer when its symbols are unresolved
+#[cfg(all(feature = "backtrace", feature = "structured-values"))]
+fn backtrace_frames(backtrace: &backtrace::Backtrace) -> serde_json::Value {
+    let mut frames = Vec::new();
+    for frame in backtrace.frames() {
+        if frame.symbols().is_empty() {
+            frames.push(serde_json::json!({ "ip": format!("{:?}", frame.ip()) }));
+        }
+        for symbol in frame.symbols() {
+            frames.push(serde_json::json!({
+                "symbol": symbol.name().map(|name| name.to_string()),
+                "file": symbol.filename().map(|file| file.display().to_string()),
+                "line": symbol.lineno(),
+            }));
+        }
+    }
+    serde_json::Value::Array(frames)
+}
+
+/// Yields a `JsonEnvLogger` builder configured to log in JSON format
+pub fn builder() -> JsonEnvLogger {
+    JsonEnvLogger::new()
+}
+
+/// Yields a `JsonEnvLogger` builder configured to log in JSON format
+/// reading filters from the provided env variable rather than `RUST_LOG`
+pub fn builder_from_env(env_var: &str) -> JsonEnvLogger {
+    JsonEnvLogger::from_env(env_logger::Env::new().filter(env_var))
+}
+
+/// Yields a `JsonEnvLogger` builder configured to log in JSON format
+/// reading filters from the provided env variable, falling back on
+/// the provided default filter, i.e. `"info"`, when the variable is not set
+pub fn builder_from_env_or(
+    env_var: &str,
+    default_filter: &str,
+) -> JsonEnvLogger {
+    JsonEnvLogger::from_env(env_logger::Env::new().filter_or(env_var, default_filter))
+}
+
+/// The value written in place of redacted kv values
+const REDACTED: &str = "[REDACTED]";
+
+/// The marker appended to truncated strings
+const TRUNCATED: &str = "…";
+
+/// The version of Elastic Common Schema written by `JsonEnvLogger::ecs`
+const ECS_VERSION: &str = "8.11.0";
+
+/// A builder for a JSON formatting logger
+///
+/// Wraps the standard `env_logger::Builder`, which remains responsible for filtering
+/// and log output targets, with options that control how records are serialized
+pub struct JsonEnvLogger {
+    builder: Builder,
+    config: Config,
+    writer: Option<Output>,
+    target: Option<env_logger::Target>,
+    is_test: bool,
+    /// the capacity of the buffer the target is wrapped in when built, if any
+    buffer_capacity: Option<usize>,
+}
+
+/// A writer shared between a logger and the code that configured it
+type SharedWriter = Arc<Mutex<dyn Write + Send>>;
+
+/// Where records are written instead of the configured `env_logger::Target`
+#[derive(Clone)]
+enum Output {
+    Shared(SharedWriter),
+    /// records at or above the level are written to the first writer, the rest to the second
+    Split(log::Level, SharedWriter, SharedWriter),
+}
+
+impl Output {
+    fn writer(
+        &self,
+        level: log::Level,
+    ) -> &SharedWriter {
+        match self {
+            Output::Shared(writer) => writer,
+            Output::Split(threshold, high, _) if level <= *threshold => high,
+            Output::Split(_, _, low) => low,
+        }
+    }
+
+    /// Wraps each writer in a `BufWriter` with the provided capacity
+    fn buffered(
+        self,
+        capacity: usize,
+    ) -> Output {
+        let buffer = |writer: SharedWriter| -> SharedWriter {
+            Arc::new(Mutex::new(io::BufWriter::with_capacity(
+                capacity,
+                Locked(writer),
+            )))
+        };
+        match self {
+            Output::Shared(writer) => Output::Shared(buffer(writer)),
+            Output::Split(threshold, high, low) => {
+                Output::Split(threshold, buffer(high), buffer(low))
             }
-            None => {
-                #[cfg(not(feature = "backtrace"))]
-                {
-                    kv_log_macro::error!("panicked at '{}'", msg, { thread: thread });
-                }
-                #[cfg(feature = "backtrace")]
-                {
-                    kv_log_macro::error!(
-                        "panicked at '{}'", msg,
-                        {
-                            thread: thread,
-                            backtrace: format!("{:?}", backtrace::Backtrace::new())
-                        }
-                    );
-                }
+        }
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let writers = match self {
+            Output::Shared(writer) => vec![writer],
+            Output::Split(_, high, low) => vec![high, low],
+        };
+        for writer in writers {
+            if let Ok(mut writer) = writer.lock() {
+                writer.flush()?;
             }
         }
-    }));
+        Ok(())
+    }
 }
 
-/// Yields the standard `env_logger::Builder` configured to log in JSON format
-pub fn builder() -> Builder {
-    let mut builder = Builder::from_default_env();
-    builder.format(write);
-    builder
+/// Writes to a shared writer, locking it for each write
+struct Locked(SharedWriter);
+
+impl Write for Locked {
+    fn write(
+        &mut self,
+        buf: &[u8],
+    ) -> io::Result<usize> {
+        match self.0.lock() {
+            Ok(mut writer) => writer.write(buf),
+            Err(_) => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.0.lock() {
+            Ok(mut writer) => writer.flush(),
+            Err(_) => Ok(()),
+        }
+    }
 }
 
-fn write<F>(
-    f: &mut F,
-    record: &log::Record,
-) -> io::Result<()>
-where
-    F: Write,
-{
-    write!(f, "{{")?;
-    write!(f, "\"level\":\"{}\",", record.level())?;
+/// Serialization options consulted by `write` for every record
+struct Config {
+    nest_fields: Option<String>,
+    expand_dotted_keys: bool,
+    level_field: String,
+    level_format: LevelFormat,
+    /// also write a `level_num` field with the syslog severity
+    level_numeric: bool,
+    message_field: String,
+    omit_empty_message: bool,
+    #[cfg(feature = "serde_json")]
+    parse_json_message: bool,
+    message_template: bool,
+    /// always holds each reserved field exactly once
+    field_order: Vec<ReservedField>,
+    max_message_len: Option<usize>,
+    strip_ansi: bool,
+    timestamp: bool,
+    timestamp_field: String,
+    timestamp_format: TimestampFormat,
+    timestamp_unit: TimestampUnit,
+    /// write ISO-8601 timestamps in the local timezone rather than UTC
+    #[cfg(feature = "chrono")]
+    timestamp_local: bool,
+    /// also write an ISO-8601 `time` field alongside unix timestamps
+    #[cfg(feature = "chrono")]
+    iso_time: bool,
+    /// fractional second digits written in ISO-8601 timestamps
+    #[cfg(feature = "chrono")]
+    timestamp_precision: chrono::SecondsFormat,
+    /// a custom format which takes precedence over `timestamp_format`
+    #[cfg(feature = "chrono")]
+    timestamp_strftime: Option<Vec<chrono::format::Item<'static>>>,
+    clock: Box<dyn Fn() -> SystemTime + Send + Sync>,
+    name: Option<String>,
+    /// written first in every record, as `v`
+    schema_version: Option<u32>,
+    pid: Option<u32>,
+    /// resolved once, when enabled
+    host: Option<String>,
+    thread_name: bool,
+    thread_id: bool,
+    /// the next sequence number, if enabled
+    sequence: Option<AtomicU64>,
+    /// when the logger was built, if uptime is enabled
+    uptime: Option<Instant>,
+    sampler: Option<Sampler>,
+    #[cfg(feature = "tracing-span-id")]
+    span_id: bool,
+    target: bool,
+    module_path: bool,
+    source_location: bool,
+    caller: bool,
+    /// static fields with values already encoded as JSON
+    fields: Vec<(String, String)>,
+    /// resource attributes written as an object under `resource_field`
+    resource: Vec<(String, String)>,
+    resource_field: String,
+    #[cfg(feature = "serde_json")]
+    injectors: Vec<Injector>,
+    transforms: Vec<Transform>,
+    float_format: FloatFormat,
+    non_finite_floats: Option<String>,
+    redacted: Vec<String>,
+    /// kv key renames, applied in the order they were added
+    renames: Vec<(String, String)>,
+    /// kv keys written after the reserved fields, under their promoted keys
+    promoted: Vec<(String, String)>,
+    max_value_len: Option<usize>,
+    /// written once, before any records
+    #[cfg(feature = "serde_json")]
+    preamble: Option<serde_json::Value>,
+    #[cfg(feature = "base64")]
+    bytes_as_base64: Vec<String>,
+    pretty: bool,
+    hybrid: bool,
+    ascii_only: bool,
+    line_terminator: Vec<u8>,
+    autoflush: bool,
+    dedup_keys: DedupStrategy,
+    sort_fields: bool,
+    reserved_keys: ReservedKeys,
+}
 
-    #[cfg(feature = "iso-timestamps")]
-    {
-        write!(
-            f,
-            "\"ts\":\"{}\"",
-            chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
-        )?;
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            nest_fields: None,
+            expand_dotted_keys: false,
+            level_field: "level".into(),
+            level_format: LevelFormat::Upper,
+            level_numeric: false,
+            message_field: "msg".into(),
+            omit_empty_message: false,
+            #[cfg(feature = "serde_json")]
+            parse_json_message: false,
+            message_template: false,
+            field_order: ReservedField::DEFAULT_ORDER.to_vec(),
+            max_message_len: None,
+            strip_ansi: false,
+            timestamp: true,
+            timestamp_field: "ts".into(),
+            timestamp_format: TimestampFormat::default(),
+            timestamp_unit: TimestampUnit::default(),
+            #[cfg(feature = "chrono")]
+            timestamp_local: false,
+            #[cfg(feature = "chrono")]
+            iso_time: false,
+            #[cfg(feature = "chrono")]
+            timestamp_precision: chrono::SecondsFormat::Millis,
+            #[cfg(feature = "chrono")]
+            timestamp_strftime: None,
+            clock: Box::new(SystemTime::now),
+            name: None,
+            schema_version: None,
+            pid: None,
+            host: None,
+            thread_name: false,
+            thread_id: false,
+            sequence: None,
+            uptime: None,
+            sampler: None,
+            #[cfg(feature = "tracing-span-id")]
+            span_id: false,
+            target: false,
+            module_path: false,
+            source_location: false,
+            caller: false,
+            fields: Vec::new(),
+            resource: Vec::new(),
+            resource_field: "resource".into(),
+            #[cfg(feature = "serde_json")]
+            injectors: Vec::new(),
+            transforms: Vec::new(),
+            float_format: FloatFormat::default(),
+            non_finite_floats: None,
+            redacted: Vec::new(),
+            renames: Vec::new(),
+            promoted: Vec::new(),
+            max_value_len: None,
+            #[cfg(feature = "serde_json")]
+            preamble: None,
+            #[cfg(feature = "base64")]
+            bytes_as_base64: Vec::new(),
+            pretty: false,
+            hybrid: false,
+            ascii_only: false,
+            line_terminator: b"\n".to_vec(),
+            autoflush: false,
+            dedup_keys: DedupStrategy::default(),
+            sort_fields: false,
+            reserved_keys: ReservedKeys::default(),
+        }
     }
-    #[cfg(not(feature = "iso-timestamps"))]
-    {
-        write!(
-            f,
-            "\"ts\":{}",
-            std::time::UNIX_EPOCH.elapsed().unwrap().as_millis()
-        )?;
+}
+
+/// Resolution of unix epoch timestamps
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TimestampUnit {
+    /// Whole seconds since the unix epoch
+    Seconds,
+    /// Milliseconds since the unix epoch. This is the default
+    #[default]
+    Millis,
+    /// Microseconds since the unix epoch
+    Micros,
+    /// Nanoseconds since the unix epoch
+    Nanos,
+}
+
+/// Writes only one of every `one_in` records at or below a level
+struct Sampler {
+    one_in: u64,
+    level: log::Level,
+    seen: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Sampler {
+    /// Yields true if the record should be written, otherwise counts it as dropped
+    fn sample(
+        &self,
+        level: log::Level,
+    ) -> bool {
+        if !self.applies(level) {
+            return true;
+        }
+        if self
+            .seen
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.one_in)
+        {
+            return true;
+        }
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        false
     }
-    write!(f, ",\"msg\":")?;
-    write_json_str(f, &record.args().to_string())?;
 
-    struct Visitor<'a, W: Write> {
-        writer: &'a mut W,
+    /// Warnings and errors are never sampled
+    fn applies(
+        &self,
+        level: log::Level,
+    ) -> bool {
+        level >= self.level && level > log::Level::Warn
     }
+}
 
-    impl<'kvs, 'a, W: Write> kv::Visitor<'kvs> for Visitor<'a, W> {
-        fn visit_pair(
-            &mut self,
-            key: kv::Key<'kvs>,
-            val: kv::Value<'kvs>,
-        ) -> Result<(), kv::Error> {
-            write!(self.writer, ",\"{}\":{}", key, val).unwrap();
-            Ok(())
+/// Yields dynamic fields for a record
+#[cfg(feature = "serde_json")]
+type Injector = Box<dyn Fn(&log::Record) -> Vec<(String, serde_json::Value)> + Send + Sync>;
+
+/// Rewrites the JSON object of a record before it's written
+type Transform = Box<dyn Fn(&mut Vec<u8>) + Send + Sync>;
+
+/// Fields written at the start of every record, in an order set with
+/// `JsonEnvLogger::field_order`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReservedField {
+    /// The level field, `level` by default
+    Level,
+    /// The timestamp field, `ts` by default
+    Timestamp,
+    /// The message field, `msg` by default
+    Message,
+}
+
+impl ReservedField {
+    const DEFAULT_ORDER: [ReservedField; 3] = [
+        ReservedField::Level,
+        ReservedField::Timestamp,
+        ReservedField::Message,
+    ];
+}
+
+/// How key values sharing the same key on a single record are written
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DedupStrategy {
+    /// Every pair is written, possibly producing duplicate JSON keys. This is the default
+    #[default]
+    KeepAll,
+    /// Only the first pair for a key is written
+    FirstWins,
+    /// Only the last pair for a key is written
+    LastWins,
+}
+
+/// The name of the current machine, or an empty string if it can't be resolved
+#[cfg(feature = "hostname")]
+fn host() -> String {
+    hostname::get()
+        .map(|hostname| hostname.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Wraps a `Duration` kv value so it's written as a number of whole milliseconds
+/// rather than a string like `1.5s`
+///
+/// ```rust
+/// use json_env_logger::Millis;
+/// use std::time::Instant;
+///
+/// let start = Instant::now();
+/// log::info!(elapsed_ms = Millis(start.elapsed()); "handled request");
+/// ```
+#[cfg(feature = "kv")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Millis(pub std::time::Duration);
+
+#[cfg(feature = "kv")]
+impl kv::ToValue for Millis {
+    fn to_value(&self) -> kv::Value<'_> {
+        kv::Value::from(self.0.as_millis())
+    }
+}
+
+/// Common bytes written after each record
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Terminator {
+    /// `\n`, as JSON Lines expects. This is the default
+    #[default]
+    Lf,
+    /// `\r\n`
+    CrLf,
+    /// Nothing, i.e. for records framed by a length prefix
+    None,
+}
+
+impl Terminator {
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Terminator::Lf => b"\n",
+            Terminator::CrLf => b"\r\n",
+            Terminator::None => b"",
         }
     }
+}
 
-    let mut visitor = Visitor { writer: f };
-    record.key_values().visit(&mut visitor).unwrap();
-    writeln!(f, "}}")
+/// How finite float kv values are written
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FloatFormat {
+    /// The shortest representation which parses back to the same value, using
+    /// scientific notation for very small or large values, i.e. `1e-7`. This is the default
+    #[default]
+    Shortest,
+    /// A decimal representation which never uses scientific notation, i.e. `0.0000001`
+    Fixed,
 }
 
-// until log kv Value impl serde::Serialize
-fn write_json_str<W: io::Write>(
-    writer: &mut W,
-    raw: &str,
-) -> std::io::Result<()> {
-    serde_json::to_writer(writer, raw)?;
-    Ok(())
+/// How key values whose keys collide with fields this crate writes,
+/// i.e. `level`, `ts` or `msg`, are written
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ReservedKeys {
+    /// Pairs are written as is, producing duplicate JSON keys. This is the default
+    #[default]
+    Keep,
+    /// Pairs are written with their key prefixed, i.e. `fields.level`
+    Prefix(String),
+    /// Pairs are omitted
+    Drop,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::error::Error;
+impl Config {
+    /// Yields true if the provided key collides with a top-level field
+    /// written for every record with the current configuration
+    #[cfg(feature = "kv")]
+    fn is_reserved(
+        &self,
+        key: &str,
+    ) -> bool {
+        let level = self.level_field.split('.').next().unwrap_or_default();
+        #[cfg(feature = "tracing-span-id")]
+        let span_id = self.span_id && key == "span_id";
+        #[cfg(not(feature = "tracing-span-id"))]
+        let span_id = false;
+        #[cfg(feature = "chrono")]
+        let time = self.timestamp && self.iso_time && key == "time";
+        #[cfg(not(feature = "chrono"))]
+        let time = false;
+        key == level
+            || (self.level_numeric && key == "level_num")
+            || key == self.message_field
+            || (self.message_template && key == "msg_template")
+            || (self.timestamp && key == self.timestamp_field)
+            || time
+            || (self.name.is_some() && key == "name")
+            || (self.schema_version.is_some() && key == "v")
+            || (self.pid.is_some() && key == "pid")
+            || (self.host.is_some() && key == "host")
+            || (self.thread_name && key == "thread")
+            || (self.thread_id && key == "thread_id")
+            || (self.sequence.is_some() && key == "seq")
+            || (self.uptime.is_some() && key == "uptime_ms")
+            || (self.sampler.is_some() && key == "sampled_out")
+            || span_id
+            || (self.target && key == "target")
+            || (self.module_path && key == "module")
+            || (self.source_location && (key == "file" || key == "line"))
+            || (self.caller && key == "caller")
+            || self.fields.iter().any(|(field, _)| field == key)
+            || (!self.resource.is_empty() && key == self.resource_field)
+            || self.promoted.iter().any(|(_, to)| to == key)
+    }
 
-    #[test]
-    fn writes_records_as_json() -> Result<(), Box<dyn Error>> {
-        let record = log::Record::builder()
-            .args(format_args!("hello"))
-            .level(log::Level::Info)
-            .build();
-        let mut buf = Vec::new();
-        write(&mut buf, &record)?;
-        let output = std::str::from_utf8(&buf)?;
-        assert!(serde_json::from_str::<serde_json::Value>(&output).is_ok());
-        Ok(())
+    #[cfg(feature = "kv")]
+    fn is_promoted(
+        &self,
+        key: &str,
+    ) -> bool {
+        self.promoted.iter().any(|(from, _)| from == key)
     }
 
-    #[test]
-    fn escapes_json_strings() -> Result<(), Box<dyn Error>> {
-        let mut buf = Vec::new();
-        write_json_str(
-            &mut buf, r#""
-	"#,
-        )?;
-        assert_eq!("\"\\\"\\n\\t\"", std::str::from_utf8(&buf)?);
+    fn is_redacted(
+        &self,
+        key: &str,
+    ) -> bool {
+        self.redacted
+            .iter()
+            .any(|redacted| redacted.eq_ignore_ascii_case(key))
+    }
+
+    #[cfg(feature = "kv")]
+    fn renamed<'k>(
+        &'k self,
+        key: &'k str,
+    ) -> &'k str {
+        self.renames
+            .iter()
+            .fold(key, |key, (from, to)| if key == from { to } else { key })
+    }
+}
+
+/// How timestamps are written
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub enum TimestampFormat {
+    /// An integer in the configured `TimestampUnit`. This is the default
+    /// unless the `iso-timestamps` feature is enabled
+    #[cfg_attr(not(feature = "iso-timestamps"), default)]
+    Unix,
+    /// An ISO-8601 (RFC 3339) string, i.e. `2020-07-06T03:45:17.831Z`.
+    /// This is the default when the `iso-timestamps` feature is enabled
+    #[cfg(feature = "chrono")]
+    #[cfg_attr(feature = "iso-timestamps", default)]
+    Iso,
+    /// An RFC 2822 string, i.e. `Mon, 6 Jul 2020 03:45:17 +0000`
+    #[cfg(feature = "chrono")]
+    Rfc2822,
+    /// An object with `seconds` and `nanos` fields, as understood by Google Cloud Logging
+    SecondsAndNanos,
+}
+
+/// How much of level names are written, set with `JsonEnvLogger::level_style`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LevelStyle {
+    /// Full names, i.e. `INFO`. This is the default
+    #[default]
+    Full,
+    /// Single letter abbreviations, i.e. `I`
+    Short,
+}
+
+/// How level names are written, i.e. `"INFO"`, `"info"` or `"Info"`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LevelFormat {
+    Upper,
+    Lower,
+    Title,
+    /// single uppercase letters, i.e. `I` for info
+    Short,
+    /// Bunyan's numeric levels, i.e. `30` for info
+    #[cfg(feature = "bunyan")]
+    Bunyan,
+    /// Google Cloud Logging's severities, i.e. `WARNING` for warn
+    Gcp,
+    /// RFC 5424 syslog numeric severities, i.e. `6` for info
+    Syslog,
+}
+
+impl LevelFormat {
+    fn write<W: Write>(
+        self,
+        writer: &mut W,
+        level: log::Level,
+    ) -> io::Result<()> {
+        self.write_quoted(writer, level, "\"")
+    }
+
+    /// Writes a level without quoting names, i.e. for hybrid prefixes
+    fn write_bare<W: Write>(
+        self,
+        writer: &mut W,
+        level: log::Level,
+    ) -> io::Result<()> {
+        self.write_quoted(writer, level, "")
+    }
+
+    fn write_quoted<W: Write>(
+        self,
+        writer: &mut W,
+        level: log::Level,
+        quote: &str,
+    ) -> io::Result<()> {
+        use log::Level::*;
+        let name = match (self, level) {
+            (LevelFormat::Upper, level) => level.as_str(),
+            (LevelFormat::Lower, Error) => "error",
+            (LevelFormat::Lower, Warn) => "warn",
+            (LevelFormat::Lower, Info) => "info",
+            (LevelFormat::Lower, Debug) => "debug",
+            (LevelFormat::Lower, Trace) => "trace",
+            (LevelFormat::Title, Error) => "Error",
+            (LevelFormat::Title, Warn) => "Warn",
+            (LevelFormat::Title, Info) => "Info",
+            (LevelFormat::Title, Debug) => "Debug",
+            (LevelFormat::Title, Trace) => "Trace",
+            (LevelFormat::Short, Error) => "E",
+            (LevelFormat::Short, Warn) => "W",
+            (LevelFormat::Short, Info) => "I",
+            (LevelFormat::Short, Debug) => "D",
+            (LevelFormat::Short, Trace) => "T",
+            (LevelFormat::Gcp, Error) => "ERROR",
+            (LevelFormat::Gcp, Warn) => "WARNING",
+            (LevelFormat::Gcp, Info) => "INFO",
+            (LevelFormat::Gcp, Debug) | (LevelFormat::Gcp, Trace) => "DEBUG",
+            #[cfg(feature = "bunyan")]
+            (LevelFormat::Bunyan, level) => {
+                let code = match level {
+                    Error => 50,
+                    Warn => 40,
+                    Info => 30,
+                    Debug => 20,
+                    Trace => 10,
+                };
+                return write!(writer, "{}", code);
+            }
+            (LevelFormat::Syslog, level) => {
+                let code = match level {
+                    Error => 3,
+                    Warn => 4,
+                    Info => 6,
+                    Debug | Trace => 7,
+                };
+                return write!(writer, "{}", code);
+            }
+        };
+        write!(writer, "{0}{1}{0}", quote, name)
+    }
+}
+
+impl JsonEnvLogger {
+    /// Creates a new builder, reading filters from the `RUST_LOG` env variable
+    pub fn new() -> Self {
+        JsonEnvLogger::from_env(env_logger::Env::default())
+    }
+
+    /// Creates a new builder, reading filters from the provided `env_logger::Env`
+    ///
+    /// See `env_logger::Builder::from_env`
+    pub fn from_env<'a, E>(env: E) -> Self
+    where
+        E: Into<env_logger::Env<'a>>,
+    {
+        JsonEnvLogger {
+            builder: Builder::from_env(env),
+            config: Config::default(),
+            writer: None,
+            target: None,
+            is_test: false,
+            buffer_capacity: None,
+        }
+    }
+
+    /// Enables or disables the timestamp field. Defaults to `true`
+    ///
+    /// Disabling this is useful when a log collector already timestamps each line
+    pub fn with_timestamp(
+        &mut self,
+        timestamp: bool,
+    ) -> &mut Self {
+        self.config.timestamp = timestamp;
+        self
+    }
+
+    /// Sets the name of the timestamp field. Defaults to `ts`
+    pub fn timestamp_field(
+        &mut self,
+        key: impl Into<String>,
+    ) -> &mut Self {
+        self.config.timestamp_field = key.into();
+        self
+    }
+
+    /// Writes timestamps as unix epoch integers with the provided resolution.
+    /// Defaults to `TimestampUnit::Millis`
+    ///
+    /// This takes precedence over the `iso-timestamps` feature
+    pub fn timestamp_unit(
+        &mut self,
+        unit: TimestampUnit,
+    ) -> &mut Self {
+        self.config.timestamp_format = TimestampFormat::Unix;
+        self.config.timestamp_unit = unit;
+        #[cfg(feature = "chrono")]
+        {
+            self.config.timestamp_strftime = None;
+        }
+        self
+    }
+
+    /// Writes ISO-8601 timestamps in the local timezone, including its offset,
+    /// i.e. `2020-07-06T13:45:17.831+10:00`, rather than UTC. Defaults to `false`
+    ///
+    /// This also applies to RFC 2822 and custom formatted timestamps but leaves
+    /// the configured `TimestampFormat` as is
+    #[cfg(feature = "iso-timestamps")]
+    pub fn local_timestamps(
+        &mut self,
+        local: bool,
+    ) -> &mut Self {
+        self.config.timestamp_local = local;
+        self
+    }
+
+    /// Enables or disables writing both a unix timestamp in milliseconds, as `ts`,
+    /// and an ISO-8601 timestamp of the same instant, as `time`. Defaults to `false`.
+    /// Disabling this restores the default ISO-8601 `ts` field
+    ///
+    /// `JsonEnvLogger::timestamp_unit` and `JsonEnvLogger::timestamp_field` still apply
+    /// to the former while `JsonEnvLogger::local_timestamps` and
+    /// `JsonEnvLogger::timestamp_precision` apply to the latter
+    #[cfg(feature = "iso-timestamps")]
+    pub fn epoch_and_iso_timestamps(
+        &mut self,
+        both: bool,
+    ) -> &mut Self {
+        let local = self.config.timestamp_local;
+        if both {
+            self.timestamp_unit(TimestampUnit::Millis);
+        } else {
+            self.timestamp_format(TimestampFormat::Iso);
+        }
+        self.config.timestamp_local = local;
+        self.config.iso_time = both;
+        self
+    }
+
+    /// Sets how timestamps are written. Defaults to `TimestampFormat::Unix`,
+    /// or `TimestampFormat::Iso` when the `iso-timestamps` feature is enabled
+    pub fn timestamp_format(
+        &mut self,
+        format: TimestampFormat,
+    ) -> &mut Self {
+        self.config.timestamp_format = format;
+        #[cfg(feature = "chrono")]
+        {
+            self.config.timestamp_strftime = None;
+        }
+        self
+    }
+
+    /// Writes timestamps with a custom `chrono` format string,
+    /// i.e. `%Y-%m-%dT%H:%M:%S%.3fZ`
+    ///
+    /// See `chrono::format::strftime` for the supported specifiers
+    ///
+    /// # panics
+    ///
+    /// Panics if the format string is invalid
+    #[cfg(feature = "iso-timestamps")]
+    pub fn timestamp_strftime(
+        &mut self,
+        fmt: &str,
+    ) -> &mut Self {
+        let items = chrono::format::StrftimeItems::new(fmt)
+            .parse_to_owned()
+            .unwrap_or_else(|_| panic!("invalid timestamp format {:?}", fmt));
+        self.config.timestamp_strftime = Some(items);
+        self
+    }
+
+    /// Sets the fractional second precision of ISO-8601 timestamps, i.e.
+    /// `chrono::SecondsFormat::Micros` for `2020-07-06T03:45:17.831000Z`.
+    /// Defaults to `chrono::SecondsFormat::Millis`
+    ///
+    /// This leaves the configured `TimestampFormat` as is
+    #[cfg(feature = "iso-timestamps")]
+    pub fn timestamp_precision(
+        &mut self,
+        precision: chrono::SecondsFormat,
+    ) -> &mut Self {
+        self.config.timestamp_precision = precision;
+        self
+    }
+
+    /// Sets the source of the current time used for timestamps. Defaults to `SystemTime::now`
+    ///
+    /// This is mostly useful for pinning timestamps to a fixed instant in tests. Times before
+    /// the unix epoch, i.e. from a misconfigured system clock, are written as the epoch
+    pub fn clock<C>(
+        &mut self,
+        clock: C,
+    ) -> &mut Self
+    where
+        C: Fn() -> SystemTime + Send + Sync + 'static,
+    {
+        self.config.clock = Box::new(clock);
+        self
+    }
+
+    /// Sets a `name` field identifying the application written with every record,
+    /// as Bunyan does
+    pub fn logger_name(
+        &mut self,
+        name: impl Into<String>,
+    ) -> &mut Self {
+        self.config.name = Some(name.into());
+        self
+    }
+
+    /// Sets a `v` field with the version of the schema of records, which consumers may
+    /// rely on for forward compatibility. Unlike other fields it's always written first
+    pub fn schema_version(
+        &mut self,
+        version: u32,
+    ) -> &mut Self {
+        self.config.schema_version = Some(version);
+        self
+    }
+
+    /// Sets a static `crate` field written with every record identifying the crate
+    /// which configured logging, typically `env!("CARGO_PKG_NAME")`
+    ///
+    /// Unlike `JsonEnvLogger::with_module_path` this is the same for every record,
+    /// regardless of which module logged it
+    ///
+    /// ```rust
+    /// json_env_logger::builder()
+    ///     .with_source_crate(env!("CARGO_PKG_NAME"))
+    ///     .init();
+    /// ```
+    pub fn with_source_crate(
+        &mut self,
+        name: &'static str,
+    ) -> &mut Self {
+        self.with_field("crate", name)
+    }
+
+    /// Writes only one of every `one_in` records at or below the provided level,
+    /// i.e. `log::Level::Debug` for debug and trace records.
+    /// Warnings and errors are always written
+    ///
+    /// Sampled records include a `sampled_out` field with the total number of records
+    /// dropped so far
+    pub fn sample(
+        &mut self,
+        one_in: u32,
+        level: log::Level,
+    ) -> &mut Self {
+        self.config.sampler = Some(Sampler {
+            one_in: u64::from(one_in.max(1)),
+            level,
+            seen: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        });
+        self
+    }
+
+    /// Enables or disables a `pid` field with the id of the current process. Defaults to `false`
+    pub fn with_pid(
+        &mut self,
+        pid: bool,
+    ) -> &mut Self {
+        self.config.pid = if pid { Some(process::id()) } else { None };
+        self
+    }
+
+    /// Enables or disables a `host` field with the name of the current machine.
+    /// Defaults to `false`
+    ///
+    /// The name is resolved once, when enabled, rather than for each record. Requires the
+    /// `hostname` feature
+    #[cfg(feature = "hostname")]
+    pub fn with_hostname(
+        &mut self,
+        hostname: bool,
+    ) -> &mut Self {
+        self.config.host = if hostname { Some(host()) } else { None };
+        self
+    }
+
+    /// Enables or disables a `thread` field with the name of the current thread. Defaults to `false`
+    pub fn with_thread_name(
+        &mut self,
+        thread_name: bool,
+    ) -> &mut Self {
+        self.config.thread_name = thread_name;
+        self
+    }
+
+    /// Enables or disables a `thread_id` field with a numeric id of the current thread.
+    /// Defaults to `false`
+    ///
+    /// Ids are assigned sequentially, starting with `1`, the first time a thread logs
+    pub fn with_thread_id(
+        &mut self,
+        thread_id: bool,
+    ) -> &mut Self {
+        self.config.thread_id = thread_id;
+        self
+    }
+
+    /// Enables or disables a `seq` field with a number incremented for each record
+    /// written by this logger, starting with `0`. Defaults to `false`
+    ///
+    /// Gaps in sequence numbers can be used to detect dropped records
+    pub fn with_sequence(
+        &mut self,
+        sequence: bool,
+    ) -> &mut Self {
+        self.config.sequence = sequence.then(|| AtomicU64::new(0));
+        self
+    }
+
+    /// Enables or disables an `uptime_ms` field with the number of milliseconds
+    /// elapsed since this logger was built. Defaults to `false`
+    pub fn with_uptime(
+        &mut self,
+        uptime: bool,
+    ) -> &mut Self {
+        self.config.uptime = uptime.then(Instant::now);
+        self
+    }
+
+    /// Enables or disables a `span_id` field with the id of the current `tracing` span,
+    /// if any. Defaults to `false`
+    ///
+    /// `tracing` has no notion of trace ids and doesn't expose the parents of a span,
+    /// so no trace id is written alongside it. Requires the `tracing-span-id` feature
+    #[cfg(feature = "tracing-span-id")]
+    pub fn with_span_id(
+        &mut self,
+        span_id: bool,
+    ) -> &mut Self {
+        self.config.span_id = span_id;
+        self
+    }
+
+    /// Enables or disables a `target` field with the target of the record. Defaults to `false`
+    pub fn with_target(
+        &mut self,
+        target: bool,
+    ) -> &mut Self {
+        self.config.target = target;
+        self
+    }
+
+    /// Enables or disables a `module` field with the module path of the record. Defaults to `false`
+    pub fn with_module_path(
+        &mut self,
+        module_path: bool,
+    ) -> &mut Self {
+        self.config.module_path = module_path;
+        self
+    }
+
+    /// Enables or disables `file` and `line` fields with the source location of the record.
+    /// Defaults to `false`
+    pub fn with_source_location(
+        &mut self,
+        source_location: bool,
+    ) -> &mut Self {
+        self.config.source_location = source_location;
+        self
+    }
+
+    /// Enables or disables a `caller` field combining the module path and source
+    /// location of the record as `module@file:line`, omitting whichever parts are unknown.
+    /// Defaults to `false`
+    pub fn with_caller(
+        &mut self,
+        caller: bool,
+    ) -> &mut Self {
+        self.config.caller = caller;
+        self
+    }
+
+    /// Adds a static field written with every record, i.e. a service name or version
+    ///
+    /// Static fields are written before a record's own kv fields. When both share a key,
+    /// both are written and most JSON parsers will keep the record's value
+    pub fn with_field(
+        &mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> &mut Self {
+        self.config
+            .fields
+            .push((key.into(), json_string(&value.into())));
+        self
+    }
+
+    /// Adds a collection of static fields written with every record
+    ///
+    /// See `JsonEnvLogger::with_field`
+    pub fn with_fields<I, K, V>(
+        &mut self,
+        fields: I,
+    ) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.config.fields.extend(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.into(), json_string(&value.into()))),
+        );
+        self
+    }
+
+    /// Writes a record describing the stream, i.e. its version or start time, to the
+    /// target when the logger is built, before any other records
+    ///
+    /// ```rust
+    /// json_env_logger::builder()
+    ///     .with_preamble(serde_json::json!({ "stream": "api", "version": 1 }))
+    ///     .init();
+    /// ```
+    #[cfg(feature = "serde_json")]
+    pub fn with_preamble(
+        &mut self,
+        preamble: serde_json::Value,
+    ) -> &mut Self {
+        self.config.preamble = Some(preamble);
+        self
+    }
+
+    /// Adds resource attributes describing the source of records, i.e. `service.name`,
+    /// written with every record as an object under a `resource` field, as
+    /// the [OpenTelemetry log data model](https://opentelemetry.io/docs/specs/otel/logs/data-model/) does
+    ///
+    /// ```rust
+    /// json_env_logger::builder()
+    ///     .with_resource(vec![
+    ///         ("service.name", "api"),
+    ///         ("service.version", env!("CARGO_PKG_VERSION")),
+    ///     ])
+    ///     .init();
+    /// ```
+    pub fn with_resource<I, K, V>(
+        &mut self,
+        attributes: I,
+    ) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.config.resource.extend(
+            attributes
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into())),
+        );
+        self
+    }
+
+    /// Sets the key of the object resource attributes are written under. Defaults to `resource`
+    pub fn resource_field(
+        &mut self,
+        key: impl Into<String>,
+    ) -> &mut Self {
+        self.config.resource_field = key.into();
+        self
+    }
+
+    /// Adds a fn which yields additional fields for each record, written after
+    /// static fields and before the record's kv fields
+    ///
+    /// This is useful for fields that aren't available at log sites,
+    /// i.e. a request id held in a thread-local
+    #[cfg(feature = "serde_json")]
+    pub fn inject_fields<F>(
+        &mut self,
+        injector: F,
+    ) -> &mut Self
+    where
+        F: Fn(&log::Record) -> Vec<(String, serde_json::Value)> + Send + Sync + 'static,
+    {
+        self.config.injectors.push(Box::new(injector));
+        self
+    }
+
+    /// Adds a fn which rewrites the bytes of each record's compact JSON object
+    /// before it's pretty printed, if enabled, and written with its line terminator,
+    /// i.e. to append a signature or wrap records in an envelope
+    ///
+    /// Transforms are applied in the order they are added. They are responsible
+    /// for leaving valid JSON behind
+    pub fn transform_line<F>(
+        &mut self,
+        transform: F,
+    ) -> &mut Self
+    where
+        F: Fn(&mut Vec<u8>) + Send + Sync + 'static,
+    {
+        self.config.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Adds a fn which rewrites each record as a `serde_json::Value`
+    ///
+    /// This is more convenient than `JsonEnvLogger::transform_line` but parses and
+    /// re-serializes every record, allocating a tree of values in between, which
+    /// typically costs more than formatting the record did. Records are written
+    /// unchanged if the transformed value can't be serialized
+    #[cfg(feature = "serde_json")]
+    pub fn transform_json<F>(
+        &mut self,
+        transform: F,
+    ) -> &mut Self
+    where
+        F: Fn(serde_json::Value) -> serde_json::Value + Send + Sync + 'static,
+    {
+        self.transform_line(move |buf| {
+            if let Ok(value) = serde_json::from_slice(buf) {
+                if let Ok(transformed) = serde_json::to_vec(&transform(value)) {
+                    *buf = transformed;
+                }
+            }
+        })
+    }
+
+    /// Nests all kv fields under an object with the provided key rather than
+    /// writing them alongside top level fields like `level`, `ts` and `msg`
+    pub fn nest_fields(
+        &mut self,
+        key: impl Into<String>,
+    ) -> &mut Self {
+        self.config.nest_fields = Some(key.into());
+        self
+    }
+
+    /// Enables or disables expanding kv fields with `.` separated keys into nested
+    /// objects, i.e. `user.id` and `user.name` into `"user":{"id":..,"name":..}`.
+    /// Defaults to `false`
+    ///
+    /// Where keys conflict, i.e. `user` and `user.id`, the later pair replaces the
+    /// earlier one, keeping its position
+    pub fn expand_dotted_keys(
+        &mut self,
+        expand: bool,
+    ) -> &mut Self {
+        self.config.expand_dotted_keys = expand;
+        self
+    }
+
+    /// Writes records in the [Bunyan](https://github.com/trentm/node-bunyan) format,
+    /// identifying the application by the provided name
+    ///
+    /// This writes Bunyan's numeric levels and its `name`, `hostname`, `pid`, `time`
+    /// and `v` fields, replacing any `hostname` field or schema version set earlier.
+    /// Requires the `bunyan` feature
+    #[cfg(feature = "bunyan")]
+    pub fn bunyan(
+        &mut self,
+        name: impl Into<String>,
+    ) -> &mut Self {
+        let hostname = json_string(&host());
+        self.config.level_field = "level".into();
+        self.config.level_format = LevelFormat::Bunyan;
+        self.config.timestamp = true;
+        self.config.timestamp_field = "time".into();
+        self.config.pid = Some(process::id());
+        self.config.schema_version = Some(0);
+        match self
+            .config
+            .fields
+            .iter_mut()
+            .find(|(key, _)| key == "hostname")
+        {
+            Some((_, existing)) => *existing = hostname,
+            None => self.config.fields.push(("hostname".into(), hostname)),
+        }
+        self.timestamp_format(TimestampFormat::Iso)
+            .logger_name(name)
+    }
+
+    /// Writes records using [Elastic Common Schema](https://www.elastic.co/guide/en/ecs/current/index.html)
+    /// field names
+    ///
+    /// This writes `@timestamp`, `log.level`, `message` and `ecs.version` fields and nests
+    /// kv fields under `labels`. Use `JsonEnvLogger::nest_fields` afterwards to choose a
+    /// different namespace for kv fields
+    pub fn ecs(&mut self) -> &mut Self {
+        self.config.level_field = "log.level".into();
+        self.config.timestamp = true;
+        self.config.timestamp_field = "@timestamp".into();
+        self.config.message_field = "message".into();
+        self.config.nest_fields = Some("labels".into());
+        self.config
+            .fields
+            .push(("ecs".into(), format!("{{\"version\":\"{}\"}}", ECS_VERSION)));
+        self
+    }
+
+    /// Writes records using [Google Cloud Logging](https://cloud.google.com/logging/docs/structured-logging)
+    /// field names
+    ///
+    /// This writes `severity`, `timestamp` and `message` fields. Cloud Logging has no trace
+    /// severity so trace records are written as `DEBUG`
+    pub fn gcp(&mut self) -> &mut Self {
+        self.config.level_field = "severity".into();
+        self.config.level_format = LevelFormat::Gcp;
+        self.config.timestamp = true;
+        self.config.timestamp_field = "timestamp".into();
+        self.config.timestamp_format = TimestampFormat::SecondsAndNanos;
+        self.config.message_field = "message".into();
+        self
+    }
+
+    /// Sets the name of the level field. Defaults to `level`
+    ///
+    /// Names containing a `.` are written as nested objects, i.e. `log.level`
+    /// is written as `"log":{"level":"INFO"}`
+    pub fn level_field(
+        &mut self,
+        key: impl Into<String>,
+    ) -> &mut Self {
+        self.config.level_field = key.into();
+        self
+    }
+
+    /// Sets the name of the message field. Defaults to `msg`
+    pub fn message_field(
+        &mut self,
+        key: impl Into<String>,
+    ) -> &mut Self {
+        self.config.message_field = key.into();
+        self
+    }
+
+    /// Enables or disables omitting the message field for records with an empty message,
+    /// i.e. those only logged for their kv fields. Defaults to `false`
+    pub fn omit_empty_message(
+        &mut self,
+        omit: bool,
+    ) -> &mut Self {
+        self.config.omit_empty_message = omit;
+        self
+    }
+
+    /// Enables or disables merging the fields of messages which are JSON objects,
+    /// i.e. `{"event":"signup"}`, into records in place of the message field.
+    /// Messages which aren't JSON objects are written as usual. Defaults to `false`
+    ///
+    /// Merged fields are written as is, without checking whether they collide with others
+    #[cfg(feature = "serde_json")]
+    pub fn parse_json_message(
+        &mut self,
+        parse: bool,
+    ) -> &mut Self {
+        self.config.parse_json_message = parse;
+        self
+    }
+
+    /// Sets the order of the fields written at the start of every record.
+    /// Defaults to `level`, `ts` then `msg`
+    ///
+    /// Fields omitted from the provided order are written after those included,
+    /// in their default order
+    pub fn field_order<I>(
+        &mut self,
+        order: I,
+    ) -> &mut Self
+    where
+        I: IntoIterator<Item = ReservedField>,
+    {
+        let mut field_order = Vec::with_capacity(ReservedField::DEFAULT_ORDER.len());
+        for field in order.into_iter().chain(ReservedField::DEFAULT_ORDER) {
+            if !field_order.contains(&field) {
+                field_order.push(field);
+            }
+        }
+        self.config.field_order = field_order;
+        self
+    }
+
+    /// Enables or disables a `msg_template` field with the record's message as written
+    /// at the log site, when it's a static string without any arguments. Defaults to `false`
+    ///
+    /// This is useful for grouping records regardless of the values they were logged with
+    pub fn with_message_template(
+        &mut self,
+        template: bool,
+    ) -> &mut Self {
+        self.config.message_template = template;
+        self
+    }
+
+    /// Writes level names in uppercase, i.e. `INFO`. This is the default
+    pub fn level_uppercase(&mut self) -> &mut Self {
+        self.config.level_format = LevelFormat::Upper;
+        self
+    }
+
+    /// Writes level names in lowercase, i.e. `info`
+    pub fn level_lowercase(&mut self) -> &mut Self {
+        self.config.level_format = LevelFormat::Lower;
+        self
+    }
+
+    /// Writes level names in titlecase, i.e. `Info`
+    pub fn level_titlecase(&mut self) -> &mut Self {
+        self.config.level_format = LevelFormat::Title;
+        self
+    }
+
+    /// Sets whether level names are written in full or abbreviated. Defaults to `LevelStyle::Full`
+    ///
+    /// | level   | `Full`  | `Short` |
+    /// |---------|---------|---------|
+    /// | `Error` | `ERROR` | `E`     |
+    /// | `Warn`  | `WARN`  | `W`     |
+    /// | `Info`  | `INFO`  | `I`     |
+    /// | `Debug` | `DEBUG` | `D`     |
+    /// | `Trace` | `TRACE` | `T`     |
+    ///
+    /// `LevelStyle::Full` writes uppercase names, as `JsonEnvLogger::level_uppercase` does
+    pub fn level_style(
+        &mut self,
+        style: LevelStyle,
+    ) -> &mut Self {
+        self.config.level_format = match style {
+            LevelStyle::Full => LevelFormat::Upper,
+            LevelStyle::Short => LevelFormat::Short,
+        };
+        self
+    }
+
+    /// Writes levels as RFC 5424 syslog numeric severities
+    ///
+    /// | level   | severity |
+    /// |---------|----------|
+    /// | `Error` | `3`      |
+    /// | `Warn`  | `4`      |
+    /// | `Info`  | `6`      |
+    /// | `Debug` | `7`      |
+    /// | `Trace` | `7`      |
+    pub fn level_as_syslog(&mut self) -> &mut Self {
+        self.config.level_format = LevelFormat::Syslog;
+        self
+    }
+
+    /// Enables or disables a `level_num` field with the level's RFC 5424 syslog
+    /// severity, as written by `JsonEnvLogger::level_as_syslog`, alongside the level
+    /// name. Defaults to `false`
+    pub fn level_numeric_and_string(
+        &mut self,
+        numeric: bool,
+    ) -> &mut Self {
+        self.config.level_numeric = numeric;
+        self
+    }
+
+    /// Truncates messages longer than the provided number of bytes, appending `…`
+    ///
+    /// Messages are truncated on a char boundary so may be slightly shorter than the limit
+    pub fn max_message_len(
+        &mut self,
+        max_len: usize,
+    ) -> &mut Self {
+        self.config.max_message_len = Some(max_len);
+        self
+    }
+
+    /// Enables or disables removing ANSI escape sequences, i.e. colors, from messages.
+    /// Defaults to `false`
+    pub fn strip_ansi(
+        &mut self,
+        strip: bool,
+    ) -> &mut Self {
+        self.config.strip_ansi = strip;
+        self
+    }
+
+    /// Redacts the values of kv fields with any of the provided keys, compared case-insensitively
+    ///
+    /// Redacted fields are still written, with a value of `"[REDACTED]"`
+    pub fn redact<I, K>(
+        &mut self,
+        keys: I,
+    ) -> &mut Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<String>,
+    {
+        self.config
+            .redacted
+            .extend(keys.into_iter().map(Into::into));
+        self
+    }
+
+    /// Renames kv fields with the provided key, i.e. `req.path` to `http.path`
+    ///
+    /// Renames compose in the order they are added, so renaming `a` to `b` then
+    /// `b` to `c` writes `a` fields as `c`. Redaction and reserved key handling
+    /// apply to the renamed key
+    pub fn rename_field(
+        &mut self,
+        from: impl Into<String>,
+        to: impl Into<String>,
+    ) -> &mut Self {
+        self.config.renames.push((from.into(), to.into()));
+        self
+    }
+
+    /// Lifts a kv field out of the other kv fields, writing it under the provided key
+    /// right after the `level`, `ts` and `msg` fields, i.e. for indexing a `span`
+    ///
+    /// Promoted fields are always written at the top level of a record, even when
+    /// fields are nested with `JsonEnvLogger::nest_fields`
+    ///
+    /// ```rust
+    /// json_env_logger::builder()
+    ///     .promote_field("scope", "span")
+    ///     .init();
+    /// ```
+    pub fn promote_field(
+        &mut self,
+        from: impl Into<String>,
+        to: impl Into<String>,
+    ) -> &mut Self {
+        self.config.promoted.push((from.into(), to.into()));
+        self
+    }
+
+    /// Truncates string kv values longer than the provided number of bytes, appending `…`
+    ///
+    /// Values are truncated on a char boundary so may be slightly shorter than the limit.
+    /// This includes values written with their `Display` impl or, with the `structured-values`
+    /// feature, serialized as strings. Other values are never truncated
+    pub fn max_value_len(
+        &mut self,
+        max_len: usize,
+    ) -> &mut Self {
+        self.config.max_value_len = Some(max_len);
+        self
+    }
+
+    /// Writes byte slice kv values with any of the provided keys, as logged, i.e.
+    /// `blob:? = &bytes[..]`, as base64 encoded strings rather than arrays of numbers.
+    /// Requires the `base64` feature
+    ///
+    /// `log` doesn't tell byte slices apart from other values so they're recognized by their
+    /// rendering, a non-empty sequence of numbers between `0` and `255`. Values with other keys
+    /// are written as is
+    #[cfg(feature = "base64")]
+    pub fn bytes_as_base64<I, K>(
+        &mut self,
+        keys: I,
+    ) -> &mut Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<String>,
+    {
+        self.config
+            .bytes_as_base64
+            .extend(keys.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets how finite float kv values are written. Defaults to `FloatFormat::Shortest`
+    ///
+    /// Some parsers don't understand scientific notation, i.e. `1e-7`
+    pub fn float_format(
+        &mut self,
+        format: FloatFormat,
+    ) -> &mut Self {
+        self.config.float_format = format;
+        self
+    }
+
+    /// Writes NaN and infinite float kv values as the provided string rather than `null`
+    ///
+    /// JSON has no representation for these values so by default they are written as `null`
+    pub fn non_finite_floats_as(
+        &mut self,
+        sentinel: impl Into<String>,
+    ) -> &mut Self {
+        self.config.non_finite_floats = Some(sentinel.into());
+        self
+    }
+
+    /// Enables or disables indented, multi-line output. Defaults to `false`
+    ///
+    /// This is intended for reading logs during local development, most log aggregators
+    /// expect one record per line
+    pub fn pretty(
+        &mut self,
+        pretty: bool,
+    ) -> &mut Self {
+        self.config.pretty = pretty;
+        self
+    }
+
+    /// Enables or disables prefixing each record with its level and message, i.e.
+    /// `INFO hello {"level":"INFO",...}`, for tools which can't parse JSON. Defaults to `false`
+    ///
+    /// Line breaks in the prefixed message are written as `\n` so records stay on one line.
+    /// Prefixes otherwise follow the configured level names, message length, ANSI stripping
+    /// and ASCII escaping of the JSON fields
+    pub fn hybrid(
+        &mut self,
+        hybrid: bool,
+    ) -> &mut Self {
+        self.config.hybrid = hybrid;
+        self
+    }
+
+    /// Enables or disables escaping all non-ASCII characters as `\uXXXX` sequences
+    /// rather than writing them as UTF-8. Defaults to `false`
+    pub fn ascii_only(
+        &mut self,
+        ascii_only: bool,
+    ) -> &mut Self {
+        self.config.ascii_only = ascii_only;
+        self
+    }
+
+    /// Sets the bytes written after each record. Defaults to `\n`
+    pub fn line_terminator(
+        &mut self,
+        terminator: &[u8],
+    ) -> &mut Self {
+        self.config.line_terminator = terminator.to_vec();
+        self
+    }
+
+    /// Sets the bytes written after each record to one of the common options.
+    /// Defaults to `Terminator::Lf`
+    ///
+    /// See `JsonEnvLogger::line_terminator` for others
+    pub fn terminator(
+        &mut self,
+        terminator: Terminator,
+    ) -> &mut Self {
+        self.line_terminator(terminator.as_bytes())
+    }
+
+    /// Sets how key values sharing the same key on a single record are written.
+    /// Defaults to `DedupStrategy::KeepAll`
+    ///
+    /// Some strict JSON parsers reject objects with duplicate keys, which may occur
+    /// when bridging from other structured logging libraries
+    pub fn dedup_keys(
+        &mut self,
+        strategy: DedupStrategy,
+    ) -> &mut Self {
+        self.config.dedup_keys = strategy;
+        self
+    }
+
+    /// Enables or disables writing key values sorted by key rather than in the order
+    /// they were logged. Defaults to `false`
+    ///
+    /// This is useful for snapshot testing, at the cost of buffering each record's key values
+    pub fn sort_fields(
+        &mut self,
+        sort: bool,
+    ) -> &mut Self {
+        self.config.sort_fields = sort;
+        self
+    }
+
+    /// Sets how key values whose keys collide with fields this crate writes,
+    /// i.e. `level`, `ts` or `msg`, are written. Defaults to `ReservedKeys::Keep`
+    ///
+    /// Key values nested with `nest_fields` never collide
+    pub fn reserved_keys(
+        &mut self,
+        policy: ReservedKeys,
+    ) -> &mut Self {
+        self.config.reserved_keys = policy;
+        self
+    }
+
+    /// Enables or disables flushing the target after each record. Defaults to `false`
+    ///
+    /// This is useful when piping output into a process that reads line by line. Note, when
+    /// enabled records are written to stdout or stderr directly rather than through env_logger,
+    /// so aren't captured by `cargo test` in `JsonEnvLogger::is_test` mode
+    pub fn autoflush(
+        &mut self,
+        autoflush: bool,
+    ) -> &mut Self {
+        self.config.autoflush = autoflush;
+        self
+    }
+
+    /// Wraps the target in a buffer holding up to the provided number of bytes, writing
+    /// records in batches rather than one at a time to reduce the number of syscalls
+    ///
+    /// Buffered records are written once the buffer fills, when records are flushed with
+    /// `JsonEnvLogger::autoflush`, or when the guard yielded by `JsonEnvLogger::init_with_guard`
+    /// is dropped. Use the guard so buffered records aren't lost when `main` returns. Note, stdout
+    /// is line buffered by `std` so without this each record is written to a terminal or pipe
+    /// as soon as it's logged, while with it a terminal won't see records until a batch is written
+    ///
+    /// ```rust
+    /// let _guard = json_env_logger::builder()
+    ///     .target(json_env_logger::env_logger::Target::Stdout)
+    ///     .buffer_capacity(64 * 1024)
+    ///     .init_with_guard();
+    ///
+    /// log::info!("👋")
+    /// ```
+    pub fn buffer_capacity(
+        &mut self,
+        capacity: usize,
+    ) -> &mut Self {
+        self.buffer_capacity = Some(capacity);
+        self
+    }
+
+    /// Adds a directive to the filter for a specific module
+    ///
+    /// See `env_logger::Builder::filter_module`
+    pub fn filter_module(
+        &mut self,
+        module: &str,
+        level: log::LevelFilter,
+    ) -> &mut Self {
+        self.builder.filter_module(module, level);
+        self
+    }
+
+    /// Adds a directive to the filter for all modules
+    ///
+    /// See `env_logger::Builder::filter_level`
+    pub fn filter_level(
+        &mut self,
+        level: log::LevelFilter,
+    ) -> &mut Self {
+        self.builder.filter_level(level);
+        self
+    }
+
+    /// Adds a directive to the filter
+    ///
+    /// See `env_logger::Builder::filter`
+    pub fn filter(
+        &mut self,
+        module: Option<&str>,
+        level: log::LevelFilter,
+    ) -> &mut Self {
+        self.builder.filter(module, level);
+        self
+    }
+
+    /// Parses the directives string in the same form as the `RUST_LOG` env variable
+    ///
+    /// See `env_logger::Builder::parse_filters`
+    pub fn parse_filters(
+        &mut self,
+        filters: &str,
+    ) -> &mut Self {
+        self.builder.parse_filters(filters);
+        self
+    }
+
+    /// Sets the target for the log output
+    ///
+    /// See `env_logger::Builder::target`
+    pub fn target(
+        &mut self,
+        target: env_logger::Target,
+    ) -> &mut Self {
+        self.builder.target(target);
+        self.target = Some(target);
+        self
+    }
+
+    /// Writes records to the provided shared writer rather than the configured
+    /// `env_logger::Target`
+    ///
+    /// This is mostly useful for reading back logged records in tests
+    pub fn with_shared_writer<W>(
+        &mut self,
+        writer: Arc<Mutex<W>>,
+    ) -> &mut Self
+    where
+        W: Write + Send + 'static,
+    {
+        self.writer = Some(Output::Shared(writer));
+        self
+    }
+
+    /// Writes records at or above the provided level, i.e. `log::Level::Warn` for
+    /// warnings and errors, to stderr and the rest to stdout
+    pub fn split_by_level(
+        &mut self,
+        threshold: log::Level,
+    ) -> &mut Self {
+        self.split_by_level_with(
+            threshold,
+            Arc::new(Mutex::new(io::stderr())),
+            Arc::new(Mutex::new(io::stdout())),
+        )
+    }
+
+    /// Writes records at or above the provided level to the first of the provided
+    /// shared writers and the rest to the second, rather than the configured `env_logger::Target`
+    pub fn split_by_level_with<H, L>(
+        &mut self,
+        threshold: log::Level,
+        high: Arc<Mutex<H>>,
+        low: Arc<Mutex<L>>,
+    ) -> &mut Self
+    where
+        H: Write + Send + 'static,
+        L: Write + Send + 'static,
+    {
+        self.writer = Some(Output::Split(threshold, high, low));
+        self
+    }
+
+    /// Writes records to each of the provided `Tee`'s writers rather than the configured
+    /// `env_logger::Target`
+    pub fn tee(
+        &mut self,
+        tee: Tee,
+    ) -> &mut Self {
+        self.with_shared_writer(Arc::new(Mutex::new(tee)))
+    }
+
+    /// Writes records to the provided rotating file rather than the configured
+    /// `env_logger::Target`
+    #[cfg(feature = "rotating-file")]
+    pub fn rotating_file(
+        &mut self,
+        target: RotatingFileTarget,
+    ) -> &mut Self {
+        self.with_shared_writer(Arc::new(Mutex::new(target)))
+    }
+
+    /// Writes records to the provided `BackgroundWriter`, which writes them on
+    /// a background thread, rather than the configured `env_logger::Target`
+    ///
+    /// Records are still formatted on the thread which logs them
+    pub fn background(
+        &mut self,
+        writer: BackgroundWriter,
+    ) -> &mut Self {
+        self.with_shared_writer(Arc::new(Mutex::new(writer)))
+    }
+
+    /// Configures the logger for unit tests
+    ///
+    /// See `env_logger::Builder::is_test`
+    pub fn is_test(
+        &mut self,
+        is_test: bool,
+    ) -> &mut Self {
+        self.builder.is_test(is_test);
+        self.is_test = is_test;
+        self
+    }
+
+    /// Register configured json env logger implementation with `log` crate.
+    ///
+    /// # panics
+    ///
+    /// Panics if logger has already been configured
+    pub fn init(&mut self) {
+        self.try_init().unwrap()
+    }
+
+    /// Register configured json env logger with `log` crate
+    ///
+    /// Will yield an `log::SetLoggerError` when a logger has already
+    /// been configured
+    pub fn try_init(&mut self) -> Result<(), log::SetLoggerError> {
+        let logger = self.build();
+        let max_level = logger.filter();
+        log::set_boxed_logger(Box::new(logger))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+
+    /// Register configured json env logger implementation with `log` crate, yielding
+    /// a guard which flushes its target when dropped
+    ///
+    /// Hold the guard for the lifetime of `main` so that records buffered by a writer,
+    /// i.e. a `BufWriter` set with `JsonEnvLogger::with_shared_writer`, aren't lost when
+    /// it returns. Note, destructors don't run when the process exits with `std::process::exit`
+    ///
+    /// ```rust
+    /// let _guard = json_env_logger::builder().init_with_guard();
+    ///
+    /// log::info!("👋")
+    /// ```
+    ///
+    /// # panics
+    ///
+    /// Panics if logger has already been configured
+    pub fn init_with_guard(&mut self) -> FlushGuard {
+        self.try_init_with_guard().unwrap()
+    }
+
+    /// Register configured json env logger with `log` crate, yielding a guard
+    /// which flushes its target when dropped
+    ///
+    /// Will yield an `log::SetLoggerError` when a logger has already
+    /// been configured
+    pub fn try_init_with_guard(&mut self) -> Result<FlushGuard, log::SetLoggerError> {
+        self.resolve_output();
+        let guard = self.flush_guard();
+        self.try_init()?;
+        Ok(guard)
+    }
+
+    /// Writes records to the target through a shared writer when they're buffered or
+    /// flushed after each record, as env_logger writes records to its target itself
+    /// without a way of flushing it. This is done before building so that a `FlushGuard`
+    /// and the logger built afterwards share the writer
+    fn resolve_output(&mut self) {
+        let capacity = self.buffer_capacity.take();
+        if capacity.is_none() && !self.config.autoflush {
+            return;
+        }
+        let output = self.writer.take().unwrap_or_else(|| {
+            let target: SharedWriter = match self.target.unwrap_or_default() {
+                env_logger::Target::Stdout => Arc::new(Mutex::new(io::stdout())),
+                env_logger::Target::Stderr => Arc::new(Mutex::new(io::stderr())),
+            };
+            Output::Shared(target)
+        });
+        self.writer = Some(match capacity {
+            Some(capacity) => output.buffered(capacity),
+            None => output,
+        });
+    }
+
+    fn flush_guard(&self) -> FlushGuard {
+        FlushGuard {
+            writer: self.writer.clone(),
+            target: self.target.unwrap_or_default(),
+        }
+    }
+
+    /// Takes the configuration for a newly built logger, starting its uptime clock
+    fn take_config(&mut self) -> Config {
+        let mut config = std::mem::take(&mut self.config);
+        if let Some(start) = &mut config.uptime {
+            *start = Instant::now();
+        }
+        config
+    }
+
+    /// Writes the preamble of a newly built logger, if any, to its target
+    fn write_preamble(
+        &self,
+        config: &Config,
+    ) {
+        #[cfg(feature = "serde_json")]
+        {
+            let line = match preamble_line(config) {
+                Some(line) => line,
+                None => return,
+            };
+            // like records, there's nobody to report a failure to write this to
+            let _ = match (&self.writer, self.target.unwrap_or_default()) {
+                (Some(output), _) => match output.writer(log::Level::Info).lock() {
+                    Ok(mut writer) => writer.write_all(&line),
+                    Err(_) => Ok(()),
+                },
+                (None, env_logger::Target::Stdout) => io::stdout().write_all(&line),
+                (None, env_logger::Target::Stderr) => io::stderr().write_all(&line),
+            };
+        }
+        #[cfg(not(feature = "serde_json"))]
+        let _ = config;
+    }
+
+    /// Builds an `env_logger::Logger` which formats records as JSON
+    /// without registering it with the `log` crate
+    pub fn build(&mut self) -> env_logger::Logger {
+        self.resolve_output();
+        let config = Arc::new(self.take_config());
+        self.write_preamble(&config);
+        format_with(&mut self.builder, config, self.writer.take());
+        self.builder.build()
+    }
+
+    /// Builds a logger which formats records as JSON along with a handle for
+    /// replacing its filters at runtime, without registering it with the `log` crate
+    ///
+    /// ```rust
+    /// let (logger, handle) = json_env_logger::builder().build_reloadable();
+    /// log::set_max_level(logger.filter());
+    /// log::set_boxed_logger(Box::new(logger)).unwrap();
+    /// // later, i.e. from an admin endpoint
+    /// handle.set_filters("debug");
+    /// ```
+    pub fn build_reloadable(&mut self) -> (ReloadableLogger, ReloadHandle) {
+        self.resolve_output();
+        let config = Arc::new(self.take_config());
+        self.write_preamble(&config);
+        let writer = self.writer.take();
+        format_with(&mut self.builder, config.clone(), writer.clone());
+        let logger = Arc::new(std::sync::RwLock::new(self.builder.build()));
+        let handle = ReloadHandle {
+            logger: logger.clone(),
+            config,
+            writer,
+            target: self.target,
+            is_test: self.is_test,
+        };
+        (ReloadableLogger { logger }, handle)
+    }
+
+    /// Builds a standalone `JsonLogger` which formats records with this builder's options,
+    /// writing those matching the provided filter to the provided writer
+    ///
+    /// The builder's own filters and target are left unused
+    ///
+    /// ```rust
+    /// let filter = json_env_logger::env_logger::filter::Builder::from_env("RUST_LOG").build();
+    /// let logger = json_env_logger::builder()
+    ///     .with_timestamp(false)
+    ///     .build_json_logger(filter, std::io::stderr());
+    /// log::set_max_level(logger.filter());
+    /// log::set_boxed_logger(Box::new(logger)).unwrap();
+    /// ```
+    pub fn build_json_logger<W>(
+        &mut self,
+        filter: Filter,
+        writer: W,
+    ) -> JsonLogger
+    where
+        W: Write + Send + 'static,
+    {
+        #[cfg_attr(not(feature = "serde_json"), allow(unused_mut))]
+        let mut writer = writer;
+        let config = self.take_config();
+        // like records, there's nobody to report a failure to write this to
+        #[cfg(feature = "serde_json")]
+        if let Some(line) = preamble_line(&config) {
+            let _ = writer.write_all(&line);
+        }
+        JsonLogger::with_config(filter, writer, config)
+    }
+}
+
+/// Encodes the preamble of a newly built logger, if any, as a line
+#[cfg(feature = "serde_json")]
+fn preamble_line(config: &Config) -> Option<Vec<u8>> {
+    let mut line = serde_json::to_vec(config.preamble.as_ref()?).unwrap_or_default();
+    line.extend_from_slice(&config.line_terminator);
+    Some(line)
+}
+
+/// Configures an `env_logger::Builder` to format records as JSON, written to
+/// the provided output, if any, rather than the builder's target
+fn format_with(
+    builder: &mut Builder,
+    config: Arc<Config>,
+    output: Option<Output>,
+) {
+    match output {
+        // env_logger writes whatever we write to its formatter to its target, which
+        // amounts to nothing when writing to a shared writer
+        Some(output) => {
+            builder.format(
+                move |_, record| match output.writer(record.level()).lock() {
+                    Ok(mut writer) => write(&mut *writer, record, &config),
+                    Err(_) => Ok(()),
+                },
+            )
+        }
+        None => builder.format(move |f, record| write(f, record, &config)),
+    };
+}
+
+/// Flushes a logger's target when dropped
+///
+/// See `JsonEnvLogger::init_with_guard`
+#[must_use = "the target is flushed as soon as the guard is dropped"]
+pub struct FlushGuard {
+    writer: Option<Output>,
+    target: env_logger::Target,
+}
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        log::logger().flush();
+        let _ = match &self.writer {
+            Some(output) => output.flush(),
+            None => match self.target {
+                env_logger::Target::Stdout => io::stdout().flush(),
+                env_logger::Target::Stderr => io::stderr().flush(),
+            },
+        };
+    }
+}
+
+/// A `log::Log` implementation whose filters may be replaced at runtime
+/// with its `ReloadHandle`
+///
+/// See `JsonEnvLogger::build_reloadable`
+pub struct ReloadableLogger {
+    logger: Arc<std::sync::RwLock<env_logger::Logger>>,
+}
+
+impl ReloadableLogger {
+    /// Returns the maximum `log::LevelFilter` this logger's filters currently allow
+    pub fn filter(&self) -> log::LevelFilter {
+        self.logger
+            .read()
+            .map(|logger| logger.filter())
+            .unwrap_or(log::LevelFilter::Off)
+    }
+}
+
+impl log::Log for ReloadableLogger {
+    fn enabled(
+        &self,
+        metadata: &log::Metadata,
+    ) -> bool {
+        self.logger
+            .read()
+            .map(|logger| logger.enabled(metadata))
+            .unwrap_or_default()
+    }
+
+    fn log(
+        &self,
+        record: &log::Record,
+    ) {
+        if let Ok(logger) = self.logger.read() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(logger) = self.logger.read() {
+            logger.flush();
+        }
+    }
+}
+
+/// Replaces the filters of a `ReloadableLogger`
+///
+/// See `JsonEnvLogger::build_reloadable`
+#[derive(Clone)]
+pub struct ReloadHandle {
+    logger: Arc<std::sync::RwLock<env_logger::Logger>>,
+    config: Arc<Config>,
+    writer: Option<Output>,
+    target: Option<env_logger::Target>,
+    is_test: bool,
+}
+
+impl ReloadHandle {
+    /// Replaces the logger's filters with those parsed from the provided directives,
+    /// in the same `RUST_LOG` syntax, i.e. `info,hyper=warn`, and updates `log`'s
+    /// max level accordingly
+    pub fn set_filters(
+        &self,
+        filters: &str,
+    ) {
+        let mut builder = Builder::new();
+        builder.parse_filters(filters).is_test(self.is_test);
+        if let Some(target) = self.target {
+            builder.target(target);
+        }
+        format_with(&mut builder, self.config.clone(), self.writer.clone());
+        let logger = builder.build();
+        log::set_max_level(logger.filter());
+        if let Ok(mut current) = self.logger.write() {
+            *current = logger;
+        }
+    }
+}
+
+impl Default for JsonEnvLogger {
+    fn default() -> Self {
+        JsonEnvLogger::new()
+    }
+}
+
+/// A standalone `log::Log` implementation which writes records matching a filter as JSON
+///
+/// Unlike `builder()`, this doesn't register itself with the `log` crate which makes it
+/// suitable for embedding in a larger logging setup, i.e. behind a multiplexing logger.
+/// Loggers created with `JsonLogger::new` or `JsonLogger::with_writer` use the default format,
+/// build one with `JsonEnvLogger::build_json_logger` to configure it
+///
+/// ```rust
+/// let filter = json_env_logger::env_logger::filter::Builder::from_env("RUST_LOG").build();
+/// let logger = json_env_logger::JsonLogger::new(filter);
+/// log::set_max_level(logger.filter());
+/// log::set_boxed_logger(Box::new(logger)).unwrap();
+/// ```
+pub struct JsonLogger {
+    filter: Filter,
+    config: Config,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonLogger {
+    /// Creates a new logger which writes records matching the provided filter to stderr
+    pub fn new(filter: Filter) -> Self {
+        JsonLogger::with_writer(filter, io::stderr())
+    }
+
+    /// Creates a new logger which writes records matching the provided filter to
+    /// the provided writer
+    pub fn with_writer<W>(
+        filter: Filter,
+        writer: W,
+    ) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        JsonLogger::with_config(filter, writer, Config::default())
+    }
+
+    fn with_config<W>(
+        filter: Filter,
+        writer: W,
+        config: Config,
+    ) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        JsonLogger {
+            filter,
+            config,
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Returns the maximum `log::LevelFilter` this logger's filter will allow
+    pub fn filter(&self) -> log::LevelFilter {
+        self.filter.filter()
+    }
+}
+
+impl log::Log for JsonLogger {
+    fn enabled(
+        &self,
+        metadata: &log::Metadata,
+    ) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(
+        &self,
+        record: &log::Record,
+    ) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = write(&mut *writer, record, &self.config);
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writer.flush();
+        }
+    }
+}
+
+/// A writer which writes everything written to it to each of several writers,
+/// i.e. both stdout and a file
+///
+/// ```rust
+/// let tee = json_env_logger::Tee::new(vec![std::io::stdout(), std::io::stdout()]);
+/// json_env_logger::builder().tee(tee).init();
+/// ```
+pub struct Tee {
+    writers: Vec<Box<dyn Write + Send>>,
+    ignore_errors: bool,
+}
+
+impl Tee {
+    /// Creates a new writer which writes to each of the provided writers
+    pub fn new<I, W>(writers: I) -> Self
+    where
+        I: IntoIterator<Item = W>,
+        W: Write + Send + 'static,
+    {
+        Tee {
+            writers: writers
+                .into_iter()
+                .map(|writer| Box::new(writer) as Box<dyn Write + Send>)
+                .collect(),
+            ignore_errors: false,
+        }
+    }
+
+    /// Enables or disables ignoring errors from individual writers. Defaults to `false`
+    ///
+    /// Either way, every writer is written to. When errors aren't ignored the first
+    /// is yielded after writing to the rest
+    pub fn ignore_errors(
+        mut self,
+        ignore: bool,
+    ) -> Self {
+        self.ignore_errors = ignore;
+        self
+    }
+
+    fn each<F>(
+        &mut self,
+        mut f: F,
+    ) -> io::Result<()>
+    where
+        F: FnMut(&mut Box<dyn Write + Send>) -> io::Result<()>,
+    {
+        let mut result = Ok(());
+        for writer in &mut self.writers {
+            if let Err(err) = f(writer) {
+                if !self.ignore_errors && result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Write for Tee {
+    fn write(
+        &mut self,
+        buf: &[u8],
+    ) -> io::Result<usize> {
+        self.each(|writer| writer.write_all(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.each(|writer| writer.flush())
+    }
+}
+
+/// Formats a record as a single line of JSON using the default options
+///
+/// This is the same format `builder()` produces without any further configuration and is
+/// useful for composing with your own `env_logger::Builder`
+///
+/// ```rust
+/// let mut builder = json_env_logger::env_logger::Builder::from_default_env();
+/// builder
+///     .filter_module("hyper", log::LevelFilter::Warn)
+///     .format(json_env_logger::json_format)
+///     .init();
+/// ```
+pub fn json_format<F>(
+    f: &mut F,
+    record: &log::Record,
+) -> io::Result<()>
+where
+    F: Write,
+{
+    static DEFAULT: OnceLock<Config> = OnceLock::new();
+    write(f, record, DEFAULT.get_or_init(Config::default))
+}
+
+/// The most capacity a thread's record buffer keeps between records, so one
+/// huge record doesn't pin its size for the life of the thread
+const MAX_RETAINED_BUF: usize = 64 * 1024;
+
+thread_local! {
+    static BUF: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+fn write<F>(
+    f: &mut F,
+    record: &log::Record,
+    config: &Config,
+) -> io::Result<()>
+where
+    F: Write + ?Sized,
+{
+    if let Some(sampler) = &config.sampler {
+        if !sampler.sample(record.level()) {
+            return Ok(());
+        }
+    }
+    BUF.with(|buf| match buf.try_borrow_mut() {
+        Ok(mut buf) => {
+            buf.clear();
+            let written = write_buffered(f, &mut buf, record, config);
+            if buf.capacity() > MAX_RETAINED_BUF {
+                buf.clear();
+                buf.shrink_to(MAX_RETAINED_BUF);
+            }
+            written
+        }
+        // a record is being written while formatting another on this thread,
+        // i.e. a kv value's `Display` impl logs
+        Err(_) => write_buffered(f, &mut Vec::new(), record, config),
+    })
+}
+
+/// Formats a record into the provided buffer and then writes it with a single `write_all`
+fn write_buffered<F>(
+    f: &mut F,
+    buf: &mut Vec<u8>,
+    record: &log::Record,
+    config: &Config,
+) -> io::Result<()>
+where
+    F: Write + ?Sized,
+{
+    write_object(buf, record, config)?;
+    for transform in &config.transforms {
+        transform(buf);
+    }
+    if config.pretty {
+        let mut pretty = Vec::with_capacity(buf.len() * 2);
+        write_pretty(&mut pretty, buf)?;
+        *buf = pretty;
+    }
+    if config.ascii_only && !buf.is_ascii() {
+        let mut ascii = Vec::with_capacity(buf.len() * 2);
+        write_ascii(&mut ascii, buf)?;
+        *buf = ascii;
+    }
+    if config.hybrid {
+        let mut prefix = Vec::new();
+        write_hybrid_prefix(&mut prefix, record, config)?;
+        buf.splice(0..0, prefix);
+    }
+    buf.extend_from_slice(&config.line_terminator);
+    // retries writes which are interrupted or only partially accepted, as pipes and sockets may
+    f.write_all(buf)?;
+    if config.autoflush {
+        f.flush()?;
+    }
+    Ok(())
+}
+
+/// Writes the human readable `LEVEL message ` prefix of a hybrid record
+fn write_hybrid_prefix<W: Write>(
+    writer: &mut W,
+    record: &log::Record,
+    config: &Config,
+) -> io::Result<()> {
+    let mut prefix = Vec::new();
+    config
+        .level_format
+        .write_bare(&mut prefix, record.level())?;
+    prefix.push(b' ');
+    let message = record.args().to_string();
+    let message = if config.strip_ansi {
+        strip_ansi(&message)
+    } else {
+        Cow::Borrowed(message.as_str())
+    };
+    let (message, truncated) = match config.max_message_len {
+        Some(max_len) => truncate(&message, max_len),
+        None => (message.as_ref(), false),
+    };
+    for line in message.split_inclusive('\n') {
+        match line.strip_suffix('\n') {
+            Some(line) => write!(prefix, "{}\\n", line.trim_end_matches('\r'))?,
+            None => prefix.extend_from_slice(line.as_bytes()),
+        }
+    }
+    if truncated {
+        prefix.extend_from_slice(TRUNCATED.as_bytes());
+    }
+    if !message.is_empty() {
+        prefix.push(b' ');
+    }
+    if config.ascii_only && !prefix.is_ascii() {
+        write_ascii(writer, &prefix)
+    } else {
+        writer.write_all(&prefix)
+    }
+}
+
+/// Writes a record as a compact JSON object
+fn write_object<F>(
+    f: &mut F,
+    record: &log::Record,
+    config: &Config,
+) -> io::Result<()>
+where
+    F: Write,
+{
+    #[cfg(feature = "serde_json")]
+    let message_object = message_object(record, config);
+    write!(f, "{{")?;
+    let mut first = true;
+    // whether kv pairs keyed by the message field would collide with it
+    #[cfg(feature = "kv")]
+    let mut wrote_message = false;
+    if let Some(version) = config.schema_version {
+        write!(f, "\"v\":{}", version)?;
+        first = false;
+    }
+    for field in &config.field_order {
+        match field {
+            ReservedField::Level => (),
+            #[cfg(feature = "serde_json")]
+            ReservedField::Message if message_object.as_ref().is_some_and(|o| o.is_empty()) => {
+                continue
+            }
+            ReservedField::Timestamp if config.timestamp => (),
+            ReservedField::Message if !(config.omit_empty_message && is_empty(record.args())) => (),
+            _ => continue,
+        }
+        if !first {
+            write!(f, ",")?;
+        }
+        first = false;
+        match field {
+            ReservedField::Level => {
+                let depth = write_key_path(f, &config.level_field)?;
+                config.level_format.write(f, record.level())?;
+                (0..depth).try_for_each(|_| write!(f, "}}"))?;
+                if config.level_numeric {
+                    write!(f, ",\"level_num\":")?;
+                    LevelFormat::Syslog.write(f, record.level())?;
+                }
+            }
+            ReservedField::Timestamp => {
+                // rather than panicking, clocks set before the epoch are written as the epoch
+                let now = (config.clock)().max(std::time::UNIX_EPOCH);
+                write_json_str(f, &config.timestamp_field)?;
+                write!(f, ":")?;
+                write_timestamp(f, config, now)?;
+                #[cfg(feature = "chrono")]
+                if config.iso_time {
+                    write!(f, ",\"time\":")?;
+                    write_iso_timestamp(f, config, now)?;
+                }
+            }
+            ReservedField::Message => {
+                #[cfg(feature = "serde_json")]
+                if let Some(object) = &message_object {
+                    for (i, (key, value)) in object.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ",")?;
+                        }
+                        write_json_str(f, key)?;
+                        write!(f, ":")?;
+                        serde_json::to_writer(&mut *f, value)?;
+                    }
+                    continue;
+                }
+                #[cfg(feature = "kv")]
+                {
+                    wrote_message = true;
+                }
+                write_json_str(f, &config.message_field)?;
+                write!(f, ":")?;
+                if config.strip_ansi {
+                    let message = record.args().to_string();
+                    let stripped = strip_ansi(&message);
+                    write_json_args(f, &format_args!("{}", stripped), config.max_message_len)?;
+                } else {
+                    write_json_args(f, record.args(), config.max_message_len)?;
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "kv")]
+    for (from, to) in &config.promoted {
+        if let Some(value) = record.key_values().get(kv::Key::from_str(from)) {
+            if !first {
+                write!(f, ",")?;
+            }
+            first = false;
+            write_json_str(f, to)?;
+            write!(f, ":")?;
+            if config.is_redacted(from) || config.is_redacted(to) {
+                write_json_str(f, REDACTED)?;
+            } else {
+                let mut writer = ErrorCapture {
+                    writer: f,
+                    error: None,
+                };
+                if let Err(err) = write_kv_value(&mut writer, from, &value, config) {
+                    return Err(writer.error.take().unwrap_or_else(|| io::Error::other(err)));
+                }
+            }
+        }
+    }
+
+    if config.message_template {
+        if let Some(template) = record.args().as_str() {
+            write!(f, ",\"msg_template\":")?;
+            write_json_str(f, template)?;
+        }
+    }
+
+    if let Some(name) = &config.name {
+        write!(f, ",\"name\":")?;
+        write_json_str(f, name)?;
+    }
+
+    if let Some(pid) = config.pid {
+        write!(f, ",\"pid\":{}", pid)?;
+    }
+
+    if let Some(host) = &config.host {
+        write!(f, ",\"host\":")?;
+        write_json_str(f, host)?;
+    }
+
+    if config.thread_name {
+        write!(f, ",\"thread\":")?;
+        write_json_str(f, thread::current().name().unwrap_or("unnamed"))?;
+    }
+
+    if config.thread_id {
+        write!(f, ",\"thread_id\":{}", current_thread_id())?;
+    }
+
+    if let Some(sampler) = config
+        .sampler
+        .as_ref()
+        .filter(|sampler| sampler.applies(record.level()))
+    {
+        write!(
+            f,
+            ",\"sampled_out\":{}",
+            sampler.dropped.load(Ordering::Relaxed)
+        )?;
+    }
+
+    if let Some(sequence) = &config.sequence {
+        write!(f, ",\"seq\":{}", sequence.fetch_add(1, Ordering::Relaxed))?;
+    }
+
+    if let Some(start) = config.uptime {
+        write!(f, ",\"uptime_ms\":{}", start.elapsed().as_millis())?;
+    }
+
+    #[cfg(feature = "tracing-span-id")]
+    {
+        if config.span_id {
+            if let Some(id) = tracing::Span::current().id() {
+                write!(f, ",\"span_id\":{}", id.into_u64())?;
+            }
+        }
+    }
+
+    if config.target {
+        write!(f, ",\"target\":")?;
+        write_json_str(f, record.target())?;
+    }
+
+    if config.module_path {
+        if let Some(module_path) = record.module_path() {
+            write!(f, ",\"module\":")?;
+            write_json_str(f, module_path)?;
+        }
+    }
+
+    if config.source_location {
+        if let Some(file) = record.file() {
+            write!(f, ",\"file\":")?;
+            write_json_str(f, file)?;
+        }
+        if let Some(line) = record.line() {
+            write!(f, ",\"line\":{}", line)?;
+        }
+    }
+
+    if config.caller {
+        if let Some(caller) = caller(record) {
+            write!(f, ",\"caller\":")?;
+            write_json_str(f, &caller)?;
+        }
+    }
+
+    for (key, value) in &config.fields {
+        write!(f, ",")?;
+        write_json_str(f, key)?;
+        write!(f, ":{}", value)?;
+    }
+
+    // with kv support, context fields are written along with the record's kv fields
+    #[cfg(not(feature = "kv"))]
+    context::with_fields(|fields| {
+        fields.iter().try_for_each(|(key, value)| {
+            write!(f, ",")?;
+            write_json_str(f, key)?;
+            write!(f, ":")?;
+            if config.is_redacted(key) {
+                write_json_str(f, REDACTED)
+            } else {
+                write_json_str(f, value)
+            }
+        })
+    })?;
+
+    if !config.resource.is_empty() {
+        write!(f, ",")?;
+        write_json_str(f, &config.resource_field)?;
+        write!(f, ":{{")?;
+        for (i, (key, value)) in config.resource.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write_json_str(f, key)?;
+            write!(f, ":")?;
+            write_json_str(f, value)?;
+        }
+        write!(f, "}}")?;
+    }
+
+    #[cfg(feature = "serde_json")]
+    for injector in &config.injectors {
+        for (key, value) in injector(record) {
+            write!(f, ",")?;
+            write_json_str(f, &key)?;
+            write!(f, ":")?;
+            if config.is_redacted(&key) {
+                write_json_str(f, REDACTED)?;
+            } else {
+                serde_json::to_writer(&mut *f, &value)?;
+            }
+        }
+    }
+
+    #[cfg(feature = "kv")]
+    write_key_values(f, record, config, wrote_message)?;
+
+    write!(f, "}}")
+}
+
+/// Writes a record's kv fields, along with any fields derived from them
+#[cfg(feature = "kv")]
+fn write_key_values<F>(
+    f: &mut F,
+    record: &log::Record,
+    config: &Config,
+    wrote_message: bool,
+) -> io::Result<()>
+where
+    F: Write,
+{
+    struct Visitor<'a, W: Write> {
+        writer: &'a mut W,
+        config: &'a Config,
+        parent: Option<&'a str>,
+        empty: bool,
+        /// fields buffered for expanding dotted keys, if enabled
+        tree: Option<Tree>,
+        /// the message field only collides with pairs when it was written
+        wrote_message: bool,
+    }
+
+    impl<W: Write> Visitor<'_, W> {
+        fn collides(
+            &self,
+            key: &str,
+        ) -> bool {
+            self.parent.is_none()
+                && self.config.is_reserved(key)
+                && (self.wrote_message || key != self.config.message_field)
+        }
+    }
+
+    impl<'kvs, 'a, W: Write> VisitSource<'kvs> for Visitor<'a, W> {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            val: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            if self.config.is_promoted(key.as_str()) {
+                return Ok(());
+            }
+            let logged = key.as_str();
+            // pairs are redacted by the key they were logged with or renamed to
+            let redacted = self.config.is_redacted(logged)
+                || self.config.is_redacted(self.config.renamed(logged));
+            let key = self.config.renamed(logged);
+            let prefix = match &self.config.reserved_keys {
+                ReservedKeys::Keep => None,
+                _ if !self.collides(key) => None,
+                ReservedKeys::Prefix(prefix) => Some(prefix.as_str()),
+                ReservedKeys::Drop => return Ok(()),
+            };
+            if let Some(tree) = &mut self.tree {
+                let key = match prefix {
+                    Some(prefix) => format!("{}{}", prefix, key),
+                    None => key.to_owned(),
+                };
+                let mut value = Vec::new();
+                if redacted {
+                    write_json_str(&mut value, REDACTED)?;
+                    tree.insert(&key, value);
+                    return Ok(());
+                }
+                write_kv_value(&mut value, logged, &val, self.config)?;
+                tree.insert(&key, value);
+                if let Some(err) = val.to_borrowed_error() {
+                    let mut chain = Vec::new();
+                    write_error_sources(&mut chain, err)?;
+                    tree.insert(&format!("{}_chain", key), chain);
+                }
+                return Ok(());
+            }
+            match self.parent {
+                Some(parent) if self.empty => {
+                    write!(self.writer, ",")?;
+                    write_json_str(self.writer, parent)?;
+                    write!(self.writer, ":{{")?;
+                }
+                _ => write!(self.writer, ",")?,
+            }
+            self.empty = false;
+            match prefix {
+                Some(prefix) => {
+                    write!(self.writer, "\"")?;
+                    write_escaped(self.writer, prefix)?;
+                    write_escaped(self.writer, key)?;
+                    write!(self.writer, "\"")?;
+                }
+                None => write_json_str(self.writer, key)?,
+            }
+            write!(self.writer, ":")?;
+            if redacted {
+                write_json_str(self.writer, REDACTED)?;
+                return Ok(());
+            }
+            write_kv_value(self.writer, logged, &val, self.config)?;
+            if let Some(err) = val.to_borrowed_error() {
+                write_error_chain(self.writer, key, err)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// A record's kv fields preceded by the current thread's context fields
+    struct WithContext<'a> {
+        context: &'a [(String, String)],
+        kvs: &'a dyn kv::Source,
+    }
+
+    impl kv::Source for WithContext<'_> {
+        fn visit<'kvs>(
+            &'kvs self,
+            visitor: &mut dyn VisitSource<'kvs>,
+        ) -> Result<(), kv::Error> {
+            for (key, value) in self.context {
+                visitor.visit_pair(kv::Key::from_str(key), kv::Value::from(value.as_str()))?;
+            }
+            self.kvs.visit(visitor)
+        }
+    }
+
+    // copied so values' `Display` impls may set context fields while we write them
+    let context = context::with_fields(<[_]>::to_vec);
+    let source = WithContext {
+        context: &context,
+        kvs: record.key_values(),
+    };
+    let mut writer = ErrorCapture {
+        writer: f,
+        error: None,
+    };
+    let mut visitor = Visitor {
+        writer: &mut writer,
+        config,
+        parent: config.nest_fields.as_deref(),
+        empty: true,
+        tree: config.expand_dotted_keys.then(Tree::default),
+        wrote_message,
+    };
+    let visited = if config.dedup_keys == DedupStrategy::KeepAll && !config.sort_fields {
+        kv::Source::visit(&source, &mut visitor)
+    } else {
+        buffer_pairs(&source, config).and_then(|pairs| {
+            pairs
+                .into_iter()
+                .try_for_each(|(key, val)| VisitSource::visit_pair(&mut visitor, key, val))
+        })
+    };
+    if let Err(err) = visited {
+        return Err(writer.error.take().unwrap_or_else(|| io::Error::other(err)));
+    }
+    let tree = visitor.tree.take();
+    if visitor.parent.is_some() && !visitor.empty {
+        write!(f, "}}")?;
+    }
+    match (tree, config.nest_fields.as_deref()) {
+        (Some(tree), _) if tree.0.is_empty() => (),
+        (Some(tree), Some(parent)) => {
+            write!(f, ",")?;
+            write_json_str(f, parent)?;
+            write!(f, ":")?;
+            tree.write(f)?;
+        }
+        (Some(tree), None) => {
+            for (key, node) in &tree.0 {
+                write!(f, ",")?;
+                write_json_str(f, key)?;
+                write!(f, ":")?;
+                node.write(f)?;
+            }
+        }
+        (None, _) => (),
+    }
+    Ok(())
+}
+
+/// Key values expanded into nested objects by the `.` separated segments of their keys
+#[derive(Default)]
+#[cfg(feature = "kv")]
+struct Tree(Vec<(String, Node)>);
+
+#[cfg(feature = "kv")]
+enum Node {
+    /// a value already encoded as JSON
+    Value(Vec<u8>),
+    Object(Tree),
+}
+
+#[cfg(feature = "kv")]
+impl Tree {
+    /// Inserts a value at the provided path, replacing any conflicting value
+    fn insert(
+        &mut self,
+        path: &str,
+        value: Vec<u8>,
+    ) {
+        let (key, rest) = match path.split_once('.') {
+            Some((key, rest)) => (key, Some(rest)),
+            None => (path, None),
+        };
+        let i = match self.0.iter().position(|(existing, _)| existing == key) {
+            Some(i) => i,
+            None => {
+                self.0.push((key.to_owned(), Node::Object(Tree::default())));
+                self.0.len() - 1
+            }
+        };
+        let node = &mut self.0[i].1;
+        match (rest, node) {
+            (None, node) => *node = Node::Value(value),
+            (Some(rest), Node::Object(tree)) => tree.insert(rest, value),
+            (Some(rest), node) => {
+                let mut tree = Tree::default();
+                tree.insert(rest, value);
+                *node = Node::Object(tree);
+            }
+        }
+    }
+
+    fn write<W: Write>(
+        &self,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        write!(writer, "{{")?;
+        for (i, (key, node)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(writer, ",")?;
+            }
+            write_json_str(writer, key)?;
+            write!(writer, ":")?;
+            node.write(writer)?;
+        }
+        write!(writer, "}}")
+    }
+}
+
+#[cfg(feature = "kv")]
+impl Node {
+    fn write<W: Write>(
+        &self,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        match self {
+            Node::Value(value) => writer.write_all(value),
+            Node::Object(tree) => tree.write(writer),
+        }
+    }
+}
+
+/// Buffers a record's key values, keeping a single pair for each key
+/// per the configured `DedupStrategy` and optionally sorting them by key
+#[cfg(feature = "kv")]
+fn buffer_pairs<'kvs>(
+    source: &'kvs dyn kv::Source,
+    config: &Config,
+) -> Result<Vec<(kv::Key<'kvs>, kv::Value<'kvs>)>, kv::Error> {
+    struct Collect<'kvs>(Vec<(kv::Key<'kvs>, kv::Value<'kvs>)>);
+
+    impl<'kvs> VisitSource<'kvs> for Collect<'kvs> {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            val: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            self.0.push((key, val));
+            Ok(())
+        }
+    }
+
+    let mut collect = Collect(Vec::new());
+    source.visit(&mut collect)?;
+    let mut pairs = collect.0;
+    // pairs are deduped and sorted by the keys they're written with
+    let key = |(key, _): &(kv::Key, kv::Value)| config.renamed(key.as_str()).to_owned();
+    match config.dedup_keys {
+        DedupStrategy::KeepAll => (),
+        DedupStrategy::FirstWins => {
+            let mut i = 0;
+            while i < pairs.len() {
+                if pairs[..i].iter().any(|pair| key(pair) == key(&pairs[i])) {
+                    pairs.remove(i);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        DedupStrategy::LastWins => {
+            let mut i = 0;
+            while i < pairs.len() {
+                if pairs[i + 1..]
+                    .iter()
+                    .any(|pair| key(pair) == key(&pairs[i]))
+                {
+                    pairs.remove(i);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+    }
+    if config.sort_fields {
+        pairs.sort_by_key(key);
+    }
+    Ok(pairs)
+}
+
+/// Yields the fields of a record's message when it's a JSON object and parsing
+/// messages is enabled
+#[cfg(feature = "serde_json")]
+fn message_object(
+    record: &log::Record,
+    config: &Config,
+) -> Option<serde_json::Map<String, serde_json::Value>> {
+    if !config.parse_json_message {
+        return None;
+    }
+    match record.args().as_str() {
+        Some(raw) => serde_json::from_str(raw).ok(),
+        None => serde_json::from_str(&record.args().to_string()).ok(),
+    }
+}
+
+/// Yields a record's `module@file:line`, or whichever parts of it are known
+fn caller(record: &log::Record) -> Option<String> {
+    let location = match (record.file(), record.line()) {
+        (Some(file), Some(line)) => Some(format!("{}:{}", file, line)),
+        (Some(file), None) => Some(file.to_owned()),
+        (None, _) => None,
+    };
+    match (record.module_path(), location) {
+        (Some(module), Some(location)) => Some(format!("{}@{}", module, location)),
+        (Some(module), None) => Some(module.to_owned()),
+        (None, location) => location,
+    }
+}
+
+/// Removes ANSI escape sequences: CSI sequences like `\x1b[31m`, OSC sequences
+/// like terminal titles and hyperlinks, and two character escapes
+fn strip_ansi(raw: &str) -> Cow<'_, str> {
+    if !raw.contains('\x1b') {
+        return Cow::Borrowed(raw);
+    }
+    let mut stripped = String::with_capacity(raw.len());
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+        match chars.next() {
+            // parameter and intermediate bytes until a final byte in `@..=~`
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // until BEL or ST, `\x1b\\`
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    Cow::Owned(stripped)
+}
+
+/// Yields true if format args format as an empty string
+fn is_empty(args: &fmt::Arguments) -> bool {
+    match args.as_str() {
+        Some(raw) => raw.is_empty(),
+        None => args.to_string().is_empty(),
+    }
+}
+
+/// A writer that holds onto the first `io::Error` its inner writer yields
+///
+/// `kv::Error` doesn't expose the `io::Error` it wraps so this lets us surface it as is
+#[cfg(feature = "kv")]
+struct ErrorCapture<'a, W: Write> {
+    writer: &'a mut W,
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "kv")]
+impl<W: Write> Write for ErrorCapture<'_, W> {
+    fn write(
+        &mut self,
+        buf: &[u8],
+    ) -> io::Result<usize> {
+        self.writer.write(buf).map_err(|err| {
+            let kind = err.kind();
+            self.error.get_or_insert(err);
+            kind.into()
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes an error's message along with the messages of its `source` chain
+/// as an array in a field named `<key>_chain`
+#[cfg(feature = "kv")]
+fn write_error_chain<W: Write>(
+    writer: &mut W,
+    key: &str,
+    err: &(dyn std::error::Error + 'static),
+) -> io::Result<()> {
+    write!(writer, ",\"")?;
+    write_escaped(writer, key)?;
+    write!(writer, "_chain\":")?;
+    write_error_sources(writer, err)
+}
+
+/// Writes an error and its chain of `source`s as an array of their messages
+#[cfg(feature = "kv")]
+fn write_error_sources<W: Write>(
+    writer: &mut W,
+    err: &(dyn std::error::Error + 'static),
+) -> io::Result<()> {
+    write!(writer, "[")?;
+    let mut source = Some(err);
+    while let Some(err) = source {
+        write_json_str(writer, &err.to_string())?;
+        source = err.source();
+        if source.is_some() {
+            write!(writer, ",")?;
+        }
+    }
+    write!(writer, "]")
+}
+
+/// Writes the key of a field, nesting an object for each `.` separated segment
+/// of the path, i.e. `"log":{"level":` for `log.level`
+///
+/// Yields the number of objects that need closing after the value is written
+fn write_key_path<W: Write>(
+    writer: &mut W,
+    path: &str,
+) -> io::Result<usize> {
+    let mut depth = 0;
+    for (i, segment) in path.split('.').enumerate() {
+        if i > 0 {
+            write!(writer, "{{")?;
+            depth += 1;
+        }
+        write_json_str(writer, segment)?;
+        write!(writer, ":")?;
+    }
+    Ok(depth)
+}
+
+/// Re-indents compact JSON, preserving the order of fields
+fn write_pretty<W: Write>(
+    writer: &mut W,
+    compact: &[u8],
+) -> io::Result<()> {
+    const INDENT: &[u8] = b"  ";
+    let mut depth = 0;
+    let mut in_str = false;
+    let mut escaped = false;
+    let mut bytes = compact.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        if in_str {
+            writer.write_all(&[byte])?;
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_str = false,
+                _ => (),
+            }
+            continue;
+        }
+        match byte {
+            b'"' => {
+                in_str = true;
+                writer.write_all(&[byte])?;
+            }
+            b'{' | b'[' => {
+                writer.write_all(&[byte])?;
+                if let Some(&close) = bytes.peek().filter(|&&next| next == b'}' || next == b']') {
+                    // keep empty objects and arrays on one line
+                    bytes.next();
+                    writer.write_all(&[close])?;
+                } else {
+                    depth += 1;
+                    writer.write_all(b"\n")?;
+                    (0..depth).try_for_each(|_| writer.write_all(INDENT))?;
+                }
+            }
+            b'}' | b']' => {
+                depth -= 1;
+                writer.write_all(b"\n")?;
+                (0..depth).try_for_each(|_| writer.write_all(INDENT))?;
+                writer.write_all(&[byte])?;
+            }
+            b',' => {
+                writer.write_all(b",\n")?;
+                (0..depth).try_for_each(|_| writer.write_all(INDENT))?;
+            }
+            b':' => writer.write_all(b": ")?,
+            _ => writer.write_all(&[byte])?,
+        }
+    }
+    Ok(())
+}
+
+/// Re-writes JSON with non-ASCII characters escaped as `\uXXXX` sequences
+///
+/// Outside of strings JSON is always ASCII so every non-ASCII character
+/// can be escaped without tracking whether we're within a string
+fn write_ascii<W: Write>(
+    writer: &mut W,
+    json: &[u8],
+) -> io::Result<()> {
+    let json =
+        std::str::from_utf8(json).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let mut start = 0;
+    for (i, c) in json.char_indices() {
+        if c.is_ascii() {
+            continue;
+        }
+        writer.write_all(&json.as_bytes()[start..i])?;
+        for unit in c.encode_utf16(&mut [0; 2]) {
+            write!(writer, "\\u{:04x}", unit)?;
+        }
+        start = i + c.len_utf8();
+    }
+    writer.write_all(&json.as_bytes()[start..])
+}
+
+/// Yields a stable numeric id for the current thread
+///
+/// `std::thread::ThreadId` doesn't expose its integer value on stable Rust
+/// so we hand out our own
+fn current_thread_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static ID: u64 = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    }
+    ID.with(|id| *id)
+}
+
+/// Writes the current time as either an ISO-8601 string or a unix epoch integer
+/// in the configured unit
+fn write_timestamp<W: Write>(
+    writer: &mut W,
+    config: &Config,
+    now: SystemTime,
+) -> io::Result<()> {
+    let elapsed = now
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    #[cfg(feature = "chrono")]
+    if let Some(items) = &config.timestamp_strftime {
+        let formatted = if config.timestamp_local {
+            chrono::DateTime::<chrono::Local>::from(now)
+                .format_with_items(items.iter())
+                .to_string()
+        } else {
+            chrono::DateTime::<chrono::Utc>::from(now)
+                .format_with_items(items.iter())
+                .to_string()
+        };
+        return write_json_str(writer, &formatted);
+    }
+    match config.timestamp_format {
+        #[cfg(feature = "chrono")]
+        TimestampFormat::Rfc2822 if config.timestamp_local => write!(
+            writer,
+            "\"{}\"",
+            chrono::DateTime::<chrono::Local>::from(now).to_rfc2822()
+        ),
+        #[cfg(feature = "chrono")]
+        TimestampFormat::Rfc2822 => write!(
+            writer,
+            "\"{}\"",
+            chrono::DateTime::<chrono::Utc>::from(now).to_rfc2822()
+        ),
+        #[cfg(feature = "chrono")]
+        TimestampFormat::Iso => write_iso_timestamp(writer, config, now),
+        TimestampFormat::SecondsAndNanos => write!(
+            writer,
+            "{{\"seconds\":{},\"nanos\":{}}}",
+            elapsed.as_secs(),
+            elapsed.subsec_nanos()
+        ),
+        TimestampFormat::Unix => match config.timestamp_unit {
+            TimestampUnit::Seconds => write!(writer, "{}", elapsed.as_secs()),
+            TimestampUnit::Millis => write!(writer, "{}", elapsed.as_millis()),
+            TimestampUnit::Micros => write!(writer, "{}", elapsed.as_micros()),
+            TimestampUnit::Nanos => write!(writer, "{}", elapsed.as_nanos()),
+        },
+    }
+}
+
+/// Writes a finite float as `serde_json` does, with the shortest digits which parse
+/// back to the same value, in scientific notation outside of `1e-5..1e16`
+#[cfg(all(feature = "kv", not(feature = "serde_json")))]
+fn write_shortest_float<W: Write>(
+    writer: &mut W,
+    value: f64,
+) -> io::Result<()> {
+    // `LowerExp` yields the shortest digits, i.e. `-1.5e-7`
+    let formatted = format!("{:e}", value);
+    let (mantissa, exp) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+    let exp: i32 = exp.parse().unwrap_or_default();
+    if !(-5..16).contains(&exp) {
+        let sign = if exp > 0 { "+" } else { "" };
+        return write!(writer, "{}e{}{}", mantissa, sign, exp);
+    }
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa),
+    };
+    let digits = mantissa.replace('.', "");
+    write!(writer, "{}", sign)?;
+    if exp < 0 {
+        return write!(writer, "0.{}{}", "0".repeat((-exp - 1) as usize), digits);
+    }
+    let point = exp as usize + 1;
+    if digits.len() <= point {
+        write!(writer, "{}{}.0", digits, "0".repeat(point - digits.len()))
+    } else {
+        write!(writer, "{}.{}", &digits[..point], &digits[point..])
+    }
+}
+
+/// Writes an ISO-8601 timestamp, in UTC or the local timezone, with the configured precision
+#[cfg(feature = "chrono")]
+fn write_iso_timestamp<W: Write>(
+    writer: &mut W,
+    config: &Config,
+    now: SystemTime,
+) -> io::Result<()> {
+    if config.timestamp_local {
+        write!(
+            writer,
+            "\"{}\"",
+            chrono::DateTime::<chrono::Local>::from(now)
+                .to_rfc3339_opts(config.timestamp_precision, false)
+        )
+    } else {
+        write!(
+            writer,
+            "\"{}\"",
+            chrono::DateTime::<chrono::Utc>::from(now)
+                .to_rfc3339_opts(config.timestamp_precision, true)
+        )
+    }
+}
+
+/// Writes a kv value as JSON, or as a base64 string when it lists bytes
+/// and its key was passed to `JsonEnvLogger::bytes_as_base64`
+#[cfg(feature = "kv")]
+#[cfg_attr(not(feature = "base64"), allow(unused_variables))]
+fn write_kv_value<W: Write>(
+    writer: &mut W,
+    key: &str,
+    value: &kv::Value,
+    config: &Config,
+) -> Result<(), kv::Error> {
+    #[cfg(feature = "base64")]
+    if config
+        .bytes_as_base64
+        .iter()
+        .any(|base64_key| base64_key == key)
+    {
+        let bytes = byte_list(&value.to_string());
+        // serde values may render differently to their json
+        #[cfg(feature = "structured-values")]
+        let bytes = bytes.or_else(|| {
+            serde_json::to_string(value)
+                .ok()
+                .and_then(|rendered| byte_list(&rendered))
+        });
+        if let Some(bytes) = bytes {
+            write!(writer, "\"{}\"", base64(&bytes))?;
+            return Ok(());
+        }
+    }
+    write_json_value(writer, value, config)
+}
+
+/// Writes a kv value as its JSON equivalent: strings are quoted and escaped
+/// while numbers, bools and null are written as bare JSON literals.
+/// Anything else falls back to an escaped string of its `Display` impl
+/// unless the `structured-values` feature is enabled, in which case it's
+/// serialized with serde
+#[cfg(feature = "kv")]
+fn write_json_value<W: Write>(
+    writer: &mut W,
+    value: &kv::Value,
+    config: &Config,
+) -> Result<(), kv::Error> {
+    struct ValueVisitor<'a, W: Write> {
+        writer: &'a mut W,
+        config: &'a Config,
+    }
+
+    impl<'v, 'a, W: Write> kv::VisitValue<'v> for ValueVisitor<'a, W> {
+        fn visit_any(
+            &mut self,
+            value: kv::Value,
+        ) -> Result<(), kv::Error> {
+            #[cfg(feature = "structured-values")]
+            match self.config.max_value_len {
+                // values serialized as strings, i.e. with `Display`, are truncated like any other
+                Some(max_len) => {
+                    let json = serde_json::to_vec(&value).map_err(kv::Error::boxed)?;
+                    match serde_json::from_slice::<String>(&json) {
+                        Ok(raw) => write_truncated_json_str(self.writer, &raw, Some(max_len))?,
+                        Err(_) => self.writer.write_all(&json)?,
+                    }
+                }
+                None => {
+                    serde_json::to_writer(&mut *self.writer, &value).map_err(kv::Error::boxed)?
+                }
+            }
+            #[cfg(not(feature = "structured-values"))]
+            {
+                write_truncated_json_str(
+                    self.writer,
+                    &value.to_string(),
+                    self.config.max_value_len,
+                )?;
+            }
+            Ok(())
+        }
+
+        fn visit_null(&mut self) -> Result<(), kv::Error> {
+            write!(self.writer, "null")?;
+            Ok(())
+        }
+
+        fn visit_u64(
+            &mut self,
+            value: u64,
+        ) -> Result<(), kv::Error> {
+            write!(self.writer, "{}", value)?;
+            Ok(())
+        }
+
+        fn visit_i64(
+            &mut self,
+            value: i64,
+        ) -> Result<(), kv::Error> {
+            write!(self.writer, "{}", value)?;
+            Ok(())
+        }
+
+        fn visit_u128(
+            &mut self,
+            value: u128,
+        ) -> Result<(), kv::Error> {
+            write!(self.writer, "{}", value)?;
+            Ok(())
+        }
+
+        fn visit_i128(
+            &mut self,
+            value: i128,
+        ) -> Result<(), kv::Error> {
+            write!(self.writer, "{}", value)?;
+            Ok(())
+        }
+
+        fn visit_f64(
+            &mut self,
+            value: f64,
+        ) -> Result<(), kv::Error> {
+            // JSON has no representation for NaN or infinity
+            match &self.config.non_finite_floats {
+                // `Display` never uses scientific notation but drops the fractional part
+                // of whole numbers, which would then parse as integers
+                _ if value.is_finite() && self.config.float_format == FloatFormat::Fixed => {
+                    if value.fract() == 0.0 {
+                        write!(self.writer, "{:.1}", value)?
+                    } else {
+                        write!(self.writer, "{}", value)?
+                    }
+                }
+                // both keep the fractional part of whole numbers, i.e. `1.0`
+                #[cfg(feature = "serde_json")]
+                _ if value.is_finite() => {
+                    serde_json::to_writer(&mut *self.writer, &value).map_err(io::Error::from)?
+                }
+                #[cfg(not(feature = "serde_json"))]
+                _ if value.is_finite() => write_shortest_float(self.writer, value)?,
+                Some(sentinel) => write_json_str(self.writer, sentinel)?,
+                None => write!(self.writer, "null")?,
+            }
+            Ok(())
+        }
+
+        fn visit_bool(
+            &mut self,
+            value: bool,
+        ) -> Result<(), kv::Error> {
+            write!(self.writer, "{}", value)?;
+            Ok(())
+        }
+
+        fn visit_str(
+            &mut self,
+            value: &str,
+        ) -> Result<(), kv::Error> {
+            write_truncated_json_str(self.writer, value, self.config.max_value_len)?;
+            Ok(())
+        }
+
+        fn visit_char(
+            &mut self,
+            value: char,
+        ) -> Result<(), kv::Error> {
+            write_json_str(self.writer, value.encode_utf8(&mut [0; 4]))?;
+            Ok(())
+        }
+    }
+
+    value.visit(ValueVisitor { writer, config })
+}
+
+/// Writes format args as a JSON string, escaping fragments as they are formatted
+/// rather than allocating an intermediate `String`
+///
+/// When a `max_len` is provided, the string is truncated to at most that many bytes,
+/// before escaping, and suffixed with `TRUNCATED`. Escaping matches that of `write_json_str`
+fn write_json_args<W: Write>(
+    writer: &mut W,
+    args: &fmt::Arguments,
+    max_len: Option<usize>,
+) -> io::Result<()> {
+    struct Escaper<'a, W: Write> {
+        writer: &'a mut W,
+        remaining: Option<usize>,
+        truncated: bool,
+        error: Option<io::Error>,
+    }
+
+    impl<'a, W: Write> fmt::Write for Escaper<'a, W> {
+        fn write_str(
+            &mut self,
+            s: &str,
+        ) -> fmt::Result {
+            if self.truncated {
+                return Ok(());
+            }
+            let s = match self.remaining {
+                Some(remaining) => {
+                    let (head, truncated) = truncate(s, remaining);
+                    self.remaining = Some(remaining - head.len());
+                    self.truncated = truncated;
+                    head
+                }
+                None => s,
+            };
+            write_escaped(self.writer, s).map_err(|err| {
+                self.error = Some(err);
+                fmt::Error
+            })
+        }
+    }
+
+    writer.write_all(b"\"")?;
+    let mut escaper = Escaper {
+        writer,
+        remaining: max_len,
+        truncated: false,
+        error: None,
+    };
+    if fmt::write(&mut escaper, *args).is_err() {
+        return Err(escaper
+            .error
+            .unwrap_or_else(|| io::Error::other("formatter error")));
+    }
+    if escaper.truncated {
+        write_escaped(writer, TRUNCATED)?;
+    }
+    writer.write_all(b"\"")
+}
+
+/// Writes a JSON string, truncated to at most `max_len` bytes before escaping
+/// and suffixed with `TRUNCATED` when a `max_len` is provided
+#[cfg(feature = "kv")]
+fn write_truncated_json_str<W: Write>(
+    writer: &mut W,
+    raw: &str,
+    max_len: Option<usize>,
+) -> io::Result<()> {
+    match max_len.map(|max_len| truncate(raw, max_len)) {
+        Some((head, true)) => {
+            writer.write_all(b"\"")?;
+            write_escaped(writer, head)?;
+            write_escaped(writer, TRUNCATED)?;
+            writer.write_all(b"\"")
+        }
+        _ => write_json_str(writer, raw),
+    }
+}
+
+/// Parses a rendered byte slice, i.e. `[1, 2, 3]` or `[1,2,3]`
+#[cfg(feature = "base64")]
+fn byte_list(rendered: &str) -> Option<Vec<u8>> {
+    let items = rendered.strip_prefix('[')?.strip_suffix(']')?;
+    if items.trim().is_empty() {
+        return None;
+    }
+    items
+        .split(',')
+        .map(|item| item.trim().parse::<u8>().ok())
+        .collect()
+}
+
+/// Encodes bytes as standard, padded base64 (RFC 4648)
+#[cfg(feature = "base64")]
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = match *chunk {
+            [a] => u32::from(a) << 16,
+            [a, b] => u32::from(a) << 16 | u32::from(b) << 8,
+            [a, b, c] => u32::from(a) << 16 | u32::from(b) << 8 | u32::from(c),
+            _ => unreachable!(),
+        };
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Splits off at most `max_len` bytes of a string on a char boundary,
+/// yielding whether anything was cut off
+fn truncate(
+    raw: &str,
+    max_len: usize,
+) -> (&str, bool) {
+    if raw.len() <= max_len {
+        return (raw, false);
+    }
+    let mut end = max_len;
+    while !raw.is_char_boundary(end) {
+        end -= 1;
+    }
+    (&raw[..end], true)
+}
+
+/// Writes the contents of a JSON string, without surrounding quotes
+fn write_escaped<W: Write>(
+    writer: &mut W,
+    raw: &str,
+) -> io::Result<()> {
+    let bytes = raw.as_bytes();
+    let mut start = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        let escape: &[u8] = match byte {
+            b'"' => b"\\\"",
+            b'\\' => b"\\\\",
+            b'\n' => b"\\n",
+            b'\r' => b"\\r",
+            b'\t' => b"\\t",
+            0x08 => b"\\b",
+            0x0c => b"\\f",
+            0x00..=0x1f => {
+                writer.write_all(&bytes[start..i])?;
+                write!(writer, "\\u{:04x}", byte)?;
+                start = i + 1;
+                continue;
+            }
+            _ => continue,
+        };
+        writer.write_all(&bytes[start..i])?;
+        writer.write_all(escape)?;
+        start = i + 1;
+    }
+    writer.write_all(&bytes[start..])
+}
+
+/// Encodes a string as a JSON string
+fn json_string(raw: &str) -> String {
+    let mut buf = Vec::new();
+    write_escaped(&mut buf, raw).expect("writes to a vec never fail");
+    format!(
+        "\"{}\"",
+        String::from_utf8(buf).expect("escaped strings are utf8")
+    )
+}
+
+/// Writes a string as a quoted and escaped JSON string, as this crate does
+/// for messages, kv keys and string values
+///
+/// This is useful for custom formatters which need consistent escaping
+///
+/// # example
+///
+/// ```rust
+/// let mut buf = Vec::new();
+/// json_env_logger::write_json_str(&mut buf, "say \"hi\"\n\tbye").unwrap();
+/// assert_eq!(buf, br#""say \"hi\"\n\tbye""#);
+/// ```
+pub fn write_json_str<W: io::Write>(
+    writer: &mut W,
+    raw: &str,
+) -> std::io::Result<()> {
+    #[cfg(feature = "serde_json")]
+    {
+        serde_json::to_writer(writer, raw)?;
+        Ok(())
+    }
+    #[cfg(not(feature = "serde_json"))]
+    {
+        writer.write_all(b"\"")?;
+        write_escaped(writer, raw)?;
+        writer.write_all(b"\"")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{error::Error, sync::Arc};
+
+    /// panic hooks are process wide so tests which register them take turns
+    static PANIC_HOOK: Mutex<()> = Mutex::new(());
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(
+            &mut self,
+            buf: &[u8],
+        ) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn write_to_json(
+        record: &log::Record,
+        config: &Config,
+    ) -> Result<serde_json::Value, Box<dyn Error>> {
+        let mut buf = Vec::new();
+        write(&mut buf, record, config)?;
+        Ok(serde_json::from_slice(&buf)?)
+    }
+
+    #[test]
+    fn writes_records_as_json() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut buf = Vec::new();
+        write(&mut buf, &record, &Config::default())?;
+        let output = std::str::from_utf8(&buf)?;
+        assert!(serde_json::from_str::<serde_json::Value>(output).is_ok());
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn writes_string_kv_values_as_json_strings() -> Result<(), Box<dyn Error>> {
+        let kvs = [("user", "bob \"the builder\"")];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let parsed = write_to_json(&record, &Config::default())?;
+        assert_eq!(parsed["user"], "bob \"the builder\"");
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn writes_bool_kv_values_as_json_bools() -> Result<(), Box<dyn Error>> {
+        let kvs = [("success", true), ("retried", false)];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let parsed = write_to_json(&record, &Config::default())?;
+        assert_eq!(parsed["success"], serde_json::Value::Bool(true));
+        assert_eq!(parsed["retried"], serde_json::Value::Bool(false));
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn writes_millis_as_json_numbers() -> Result<(), Box<dyn Error>> {
+        let kvs = [(
+            "elapsed_ms",
+            Millis(std::time::Duration::from_micros(1_500_250)),
+        )];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let parsed = write_to_json(&record, &Config::default())?;
+        assert_eq!(parsed["elapsed_ms"], 1500);
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn writes_numeric_kv_values_as_json_numbers() -> Result<(), Box<dyn Error>> {
+        let count = 42;
+        let ratio = 2.5;
+        let whole = 1.0;
+        let kvs: [(&str, &dyn kv::ToValue); 4] = [
+            ("count", &count),
+            ("ratio", &ratio),
+            ("whole", &whole),
+            ("negative", &-7i8),
+        ];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let parsed = write_to_json(&record, &Config::default())?;
+        assert_eq!(parsed["count"], 42);
+        assert!(parsed["count"].is_u64());
+        assert_eq!(parsed["ratio"], 2.5);
+        assert!(parsed["whole"].is_f64());
+        assert_eq!(parsed["negative"], -7);
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn writes_floats_in_configured_format() -> Result<(), Box<dyn Error>> {
+        let kvs: [(&str, &dyn kv::ToValue); 3] =
+            [("small", &1e-7), ("large", &1e21), ("whole", &1.0)];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.with_timestamp(false);
+        for (format, expected) in [
+            (
+                FloatFormat::Shortest,
+                r#""small":1e-7,"large":1e+21,"whole":1.0}"#,
+            ),
+            (
+                FloatFormat::Fixed,
+                r#""small":0.0000001,"large":1000000000000000000000.0,"whole":1.0}"#,
+            ),
+        ] {
+            builder.float_format(format);
+            let mut buf = Vec::new();
+            write(&mut buf, &record, &builder.config)?;
+            let output = std::str::from_utf8(&buf)?;
+            assert!(output.ends_with(&format!("{}\n", expected)), "{}", output);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn writes_none_kv_values_as_json_null() -> Result<(), Box<dyn Error>> {
+        let user: Option<&str> = None;
+        let kvs: [(&str, &dyn kv::ToValue); 2] = [("user", &user), ("unit", &kv::Value::null())];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let parsed = write_to_json(&record, &Config::default())?;
+        assert!(parsed["user"].is_null());
+        assert!(parsed["unit"].is_null());
+        assert!(parsed.get("user").is_some());
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn writes_stable_kv_values() -> Result<(), Box<dyn Error>> {
+        let kvs: [(&str, kv::Value); 5] = [
+            ("str", kv::Value::from("bob")),
+            ("int", kv::Value::from(42)),
+            ("float", kv::Value::from(1.5)),
+            ("bool", kv::Value::from(true)),
+            ("display", kv::Value::from_display(&'c')),
+        ];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let config = Config {
+            timestamp: false,
+            ..Config::default()
+        };
+        let mut buf = Vec::new();
+        write(&mut buf, &record, &config)?;
+        assert_eq!(
+            std::str::from_utf8(&buf)?,
+            "{\"level\":\"INFO\",\"msg\":\"hello\",\"str\":\"bob\",\"int\":42,\"float\":1.5,\"bool\":true,\"display\":\"c\"}\n"
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn escapes_kv_keys() -> Result<(), Box<dyn Error>> {
+        let kvs = [("a\"b", 1)];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let parsed = write_to_json(&record, &Config::default())?;
+        assert_eq!(parsed["a\"b"], 1);
+        Ok(())
+    }
+
+    #[cfg(feature = "base64")]
+    #[test]
+    fn writes_byte_slices_as_base64() -> Result<(), Box<dyn Error>> {
+        fn decode(encoded: &str) -> Vec<u8> {
+            const ALPHABET: &str =
+                "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+            let sextets: Vec<u32> = encoded
+                .trim_end_matches('=')
+                .chars()
+                .map(|c| ALPHABET.find(c).unwrap() as u32)
+                .collect();
+            let mut decoded = Vec::new();
+            for chunk in sextets.chunks(4) {
+                let group = chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0, |group, (i, sextet)| group | sextet << (18 - 6 * i));
+                decoded.extend(&group.to_be_bytes()[1..chunk.len()]);
+            }
+            decoded
+        }
+
+        for bytes in [&b"\x00\x01\xfe\xff"[..], b"hi", b"json"] {
+            let kvs = [("blob", kv::Value::from_debug(&bytes))];
+            let record = log::Record::builder()
+                .args(format_args!("hello"))
+                .level(log::Level::Info)
+                .key_values(&kvs)
+                .build();
+            let mut builder = JsonEnvLogger::new();
+            builder.bytes_as_base64(["blob"]);
+            let parsed = write_to_json(&record, &builder.config)?;
+            let encoded = parsed["blob"].as_str().unwrap();
+            assert_eq!(encoded.len() % 4, 0);
+            assert_eq!(decode(encoded), bytes);
+        }
+        // only the provided keys are written as base64
+        let ids = [1u8, 2, 3];
+        let kvs = [("ids", kv::Value::from_debug(&ids))];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.bytes_as_base64(["blob"]);
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert_eq!(parsed["ids"], "[1, 2, 3]");
+        #[cfg(feature = "structured-values")]
+        {
+            let bytes = b"serde".to_vec();
+            let kvs = [("blob", kv::Value::from_serde(&bytes))];
+            let record = log::Record::builder()
+                .args(format_args!("hello"))
+                .level(log::Level::Info)
+                .key_values(&kvs)
+                .build();
+            let mut builder = JsonEnvLogger::new();
+            builder.bytes_as_base64(["blob"]);
+            let parsed = write_to_json(&record, &builder.config)?;
+            assert_eq!(decode(parsed["blob"].as_str().unwrap()), bytes);
+        }
+        assert_eq!(base64(b"hi"), "aGk=");
+        Ok(())
+    }
+
+    #[cfg(feature = "structured-values")]
+    #[test]
+    fn writes_structured_kv_values_as_json() -> Result<(), Box<dyn Error>> {
+        let payload = serde_json::json!({ "id": 1, "tags": ["a", "b"] });
+        let kvs = [("payload", kv::Value::from_serde(&payload))];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let parsed = write_to_json(&record, &Config::default())?;
+        assert_eq!(parsed["payload"], payload);
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn promotes_kv_fields_after_reserved_fields() -> Result<(), Box<dyn Error>> {
+        let kvs = [("user", "bob"), ("span", "handle_request")];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .with_timestamp(false)
+            .nest_fields("fields")
+            .promote_field("span", "span");
+        let mut buf = Vec::new();
+        write(&mut buf, &record, &builder.config)?;
+        assert_eq!(
+            std::str::from_utf8(&buf)?.trim_end(),
+            r#"{"level":"INFO","msg":"hello","span":"handle_request","fields":{"user":"bob"}}"#
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn redacts_promoted_fields_by_either_key() -> Result<(), Box<dyn Error>> {
+        let kvs = [("password", "hunter2"), ("token", "abc")];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .redact(vec!["pw", "token"])
+            .promote_field("password", "pw")
+            .promote_field("token", "auth");
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert_eq!(parsed["pw"], REDACTED);
+        assert_eq!(parsed["auth"], REDACTED);
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn nests_kv_fields_under_parent_key() -> Result<(), Box<dyn Error>> {
+        let kvs = [("user", "bob")];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let config = Config {
+            nest_fields: Some("fields".into()),
+            ..Config::default()
+        };
+        let parsed = write_to_json(&record, &config)?;
+        assert_eq!(parsed["fields"]["user"], "bob");
+        assert!(parsed.get("user").is_none());
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn expands_dotted_keys_into_nested_objects() -> Result<(), Box<dyn Error>> {
+        let kvs = [
+            ("user.id", "42"),
+            ("user.name", "bob"),
+            ("req", "scalar"),
+            ("req.path", "/x"),
+        ];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.with_timestamp(false).expand_dotted_keys(true);
+        let mut buf = Vec::new();
+        write(&mut buf, &record, &builder.config)?;
+        assert_eq!(
+            std::str::from_utf8(&buf)?,
+            "{\"level\":\"INFO\",\"msg\":\"hello\",\"user\":{\"id\":\"42\",\"name\":\"bob\"},\"req\":{\"path\":\"/x\"}}\n"
+        );
+
+        builder.nest_fields("fields");
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert_eq!(parsed["fields"]["user"]["name"], "bob");
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn redacts_prefixed_keys_when_expanding_dotted_keys() -> Result<(), Box<dyn Error>> {
+        let kvs = [("level", "x"), ("user.password", "hunter2")];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .with_timestamp(false)
+            .expand_dotted_keys(true)
+            .reserved_keys(ReservedKeys::Prefix("fields.".into()))
+            .redact(vec!["level", "user.password"]);
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert_eq!(parsed["fields"]["level"], REDACTED);
+        assert_eq!(parsed["user"]["password"], REDACTED);
+        Ok(())
+    }
+
+    #[test]
+    fn writes_custom_level_field_and_case() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        for (format, expected) in &[
+            (LevelFormat::Upper, "INFO"),
+            (LevelFormat::Lower, "info"),
+            (LevelFormat::Title, "Info"),
+        ] {
+            let config = Config {
+                level_field: "severity".into(),
+                level_format: *format,
+                ..Config::default()
+            };
+            let parsed = write_to_json(&record, &config)?;
+            assert_eq!(parsed["severity"], *expected);
+            assert!(parsed.get("level").is_none());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn writes_syslog_severities() -> Result<(), Box<dyn Error>> {
+        let mut builder = JsonEnvLogger::new();
+        builder.level_as_syslog();
+        for (level, expected) in [
+            (log::Level::Error, 3),
+            (log::Level::Warn, 4),
+            (log::Level::Info, 6),
+            (log::Level::Debug, 7),
+            (log::Level::Trace, 7),
+        ] {
+            let parsed = write_to_json(
+                &log::Record::builder()
+                    .args(format_args!("hello"))
+                    .level(level)
+                    .build(),
+                &builder.config,
+            )?;
+            assert_eq!(parsed["level"], expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn writes_short_level_names() -> Result<(), Box<dyn Error>> {
+        let mut builder = JsonEnvLogger::new();
+        builder.level_style(LevelStyle::Short);
+        for (level, expected) in [
+            (log::Level::Error, "E"),
+            (log::Level::Warn, "W"),
+            (log::Level::Info, "I"),
+            (log::Level::Debug, "D"),
+            (log::Level::Trace, "T"),
+        ] {
+            let parsed = write_to_json(
+                &log::Record::builder()
+                    .args(format_args!("hello"))
+                    .level(level)
+                    .build(),
+                &builder.config,
+            )?;
+            assert_eq!(parsed["level"], expected);
+        }
+        builder.level_style(LevelStyle::Full);
+        let parsed = write_to_json(
+            &log::Record::builder()
+                .args(format_args!("hello"))
+                .level(log::Level::Warn)
+                .build(),
+            &builder.config,
+        )?;
+        assert_eq!(parsed["level"], "WARN");
+        Ok(())
+    }
+
+    #[test]
+    fn writes_level_names_and_numbers_when_enabled() -> Result<(), Box<dyn Error>> {
+        let mut builder = JsonEnvLogger::new();
+        builder.level_numeric_and_string(true);
+        for (level, expected) in [
+            (log::Level::Error, 3),
+            (log::Level::Warn, 4),
+            (log::Level::Info, 6),
+            (log::Level::Debug, 7),
+            (log::Level::Trace, 7),
+        ] {
+            let parsed = write_to_json(
+                &log::Record::builder()
+                    .args(format_args!("hello"))
+                    .level(level)
+                    .build(),
+                &builder.config,
+            )?;
+            assert_eq!(parsed["level"], level.as_str());
+            assert_eq!(parsed["level_num"], expected);
+        }
+        assert!(write_to_json(
+            &log::Record::builder()
+                .args(format_args!("hello"))
+                .level(log::Level::Info)
+                .build(),
+            &Config::default()
+        )?
+        .get("level_num")
+        .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn writes_custom_message_field() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.message_field("message");
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert_eq!(parsed["message"], "hello");
+        assert!(parsed.get("msg").is_none());
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn omits_empty_messages_when_enabled() -> Result<(), Box<dyn Error>> {
+        let kvs = [("event", "tick")];
+        let record = log::Record::builder()
+            .args(format_args!(""))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        assert_eq!(write_to_json(&record, &Config::default())?["msg"], "");
+        let mut builder = JsonEnvLogger::new();
+        builder.omit_empty_message(true);
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert!(parsed.get("msg").is_none());
+        assert_eq!(parsed["event"], "tick");
+        Ok(())
+    }
+
+    #[cfg(all(feature = "kv", feature = "serde_json"))]
+    #[test]
+    fn merges_json_object_messages_when_enabled() -> Result<(), Box<dyn Error>> {
+        let mut builder = JsonEnvLogger::new();
+        builder.with_timestamp(false).parse_json_message(true);
+        let kvs = [("user", "bob")];
+        let mut buf = Vec::new();
+        write(
+            &mut buf,
+            &log::Record::builder()
+                .args(format_args!("{}", r#"{"event":"signup","attempts":2}"#))
+                .level(log::Level::Info)
+                .key_values(&kvs)
+                .build(),
+            &builder.config,
+        )?;
+        assert_eq!(
+            std::str::from_utf8(&buf)?,
+            "{\"level\":\"INFO\",\"attempts\":2,\"event\":\"signup\",\"user\":\"bob\"}\n"
+        );
+        let parse = |message: &str| {
+            write_to_json(
+                &log::Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(log::Level::Info)
+                    .build(),
+                &builder.config,
+            )
+        };
+        assert_eq!(parse("[1,2]")?["msg"], "[1,2]");
+        assert_eq!(parse("{nope")?["msg"], "{nope");
+        assert!(parse("{}")?.get("msg").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn writes_message_template_when_enabled() -> Result<(), Box<dyn Error>> {
+        let mut builder = JsonEnvLogger::new();
+        builder.with_message_template(true);
+        let parsed = write_to_json(
+            &log::Record::builder()
+                .args(format_args!("user signed in"))
+                .level(log::Level::Info)
+                .build(),
+            &builder.config,
+        )?;
+        assert_eq!(parsed["msg_template"], "user signed in");
+        let user = "bob";
+        let parsed = write_to_json(
+            &log::Record::builder()
+                .args(format_args!("{} signed in", user))
+                .level(log::Level::Info)
+                .build(),
+            &builder.config,
+        )?;
+        assert!(parsed.get("msg_template").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn writes_reserved_fields_in_configured_order() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .timestamp_unit(TimestampUnit::Seconds)
+            .clock(|| std::time::UNIX_EPOCH + std::time::Duration::from_secs(1))
+            .field_order([ReservedField::Timestamp, ReservedField::Message]);
+        let mut buf = Vec::new();
+        write(&mut buf, &record, &builder.config)?;
+        assert_eq!(
+            std::str::from_utf8(&buf)?.trim_end(),
+            r#"{"ts":1,"msg":"hello","level":"INFO"}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn writes_custom_timestamp_field() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let config = Config {
+            timestamp_field: "time".into(),
+            ..Config::default()
+        };
+        let parsed = write_to_json(&record, &config)?;
+        assert!(parsed.get("time").is_some());
+        assert!(parsed.get("ts").is_none());
+        Ok(())
+    }
+
+    #[cfg(not(feature = "kv"))]
+    #[test]
+    fn writes_only_reserved_fields_without_kv() -> Result<(), Box<dyn Error>> {
+        let kvs = [("user", "bob")];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let parsed = write_to_json(&record, &Config::default())?;
+        let mut keys = parsed
+            .as_object()
+            .ok_or("not an object")?
+            .keys()
+            .collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, ["level", "msg", "ts"]);
+        assert_eq!(parsed["msg"], "hello");
+        Ok(())
+    }
+
+    #[test]
+    fn omits_timestamp_when_disabled() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let config = Config {
+            timestamp: false,
+            ..Config::default()
+        };
+        let parsed = write_to_json(&record, &config)?;
+        assert!(parsed.get("ts").is_none());
+        assert_eq!(parsed["msg"], "hello");
+        Ok(())
+    }
+
+    #[test]
+    fn writes_second_timestamps() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.timestamp_unit(TimestampUnit::Seconds);
+        let now = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        let ts = write_to_json(&record, &builder.config)?["ts"]
+            .as_u64()
+            .unwrap_or_default();
+        assert_eq!(ts.to_string().len(), 10);
+        assert!(ts.abs_diff(now) <= 1);
+        Ok(())
+    }
+
+    #[test]
+    fn writes_nanosecond_timestamps() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let config = Config {
+            timestamp_format: TimestampFormat::Unix,
+            timestamp_unit: TimestampUnit::Nanos,
+            ..Config::default()
+        };
+        let first = write_to_json(&record, &config)?;
+        let second = write_to_json(&record, &config)?;
+        assert!(first["ts"].is_number());
+        assert_ne!(first["ts"], second["ts"]);
+        Ok(())
+    }
+
+    #[test]
+    fn writes_timestamps_from_configured_clock() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let config = Config {
+            clock: Box::new(|| {
+                std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_594_007_117_831)
+            }),
+            ..Config::default()
+        };
+        let parsed = write_to_json(&record, &config)?;
+        #[cfg(feature = "iso-timestamps")]
+        assert_eq!(parsed["ts"], "2020-07-06T03:45:17.831Z");
+        #[cfg(not(feature = "iso-timestamps"))]
+        assert_eq!(parsed["ts"], 1_594_007_117_831u64);
+        Ok(())
+    }
+
+    #[test]
+    fn writes_pre_epoch_clocks_as_the_epoch() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let config = Config {
+            clock: Box::new(|| std::time::UNIX_EPOCH - std::time::Duration::from_secs(60)),
+            ..Config::default()
+        };
+        let parsed = write_to_json(&record, &config)?;
+        #[cfg(feature = "iso-timestamps")]
+        assert_eq!(parsed["ts"], "1970-01-01T00:00:00.000Z");
+        #[cfg(not(feature = "iso-timestamps"))]
+        assert_eq!(parsed["ts"], 0);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "iso-timestamps")]
+    fn writes_local_timestamps_with_offset() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.local_timestamps(true);
+        let parsed = write_to_json(&record, &builder.config)?;
+        let ts = parsed["ts"].as_str().unwrap_or_default();
+        let offset = &ts[ts.len() - 6..];
+        assert!(offset.starts_with('+') || offset.starts_with('-'));
+        assert_eq!(&offset[3..4], ":");
+        assert!(chrono::DateTime::parse_from_rfc3339(ts).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "iso-timestamps")]
+    fn writes_timestamps_with_configured_precision() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        for (precision, expected) in [
+            (chrono::SecondsFormat::Secs, "2020-07-06T03:45:17Z"),
+            (chrono::SecondsFormat::Millis, "2020-07-06T03:45:17.831Z"),
+            (chrono::SecondsFormat::Micros, "2020-07-06T03:45:17.831234Z"),
+            (
+                chrono::SecondsFormat::Nanos,
+                "2020-07-06T03:45:17.831234567Z",
+            ),
+        ] {
+            let mut builder = JsonEnvLogger::new();
+            builder.timestamp_precision(precision).clock(|| {
+                std::time::UNIX_EPOCH + std::time::Duration::from_nanos(1_594_007_117_831_234_567)
+            });
+            let parsed = write_to_json(&record, &builder.config)?;
+            assert_eq!(parsed["ts"], expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "iso-timestamps")]
+    fn writes_epoch_and_iso_timestamps_when_enabled() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.epoch_and_iso_timestamps(true);
+        let parsed = write_to_json(&record, &builder.config)?;
+        let millis = parsed["ts"].as_i64().ok_or("expected millis")?;
+        let time =
+            chrono::DateTime::parse_from_rfc3339(parsed["time"].as_str().unwrap_or_default())?;
+        assert_eq!(time.timestamp_millis(), millis);
+
+        builder.epoch_and_iso_timestamps(false);
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert!(parsed["ts"].is_string());
+        assert!(parsed.get("time").is_none());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "iso-timestamps")]
+    fn writes_rfc2822_timestamps() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .timestamp_format(TimestampFormat::Rfc2822)
+            .clock(|| std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_594_007_117_831));
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert_eq!(parsed["ts"], "Mon, 6 Jul 2020 03:45:17 +0000");
+        assert!(
+            chrono::DateTime::parse_from_rfc2822(parsed["ts"].as_str().unwrap_or_default()).is_ok()
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "iso-timestamps")]
+    fn keeps_timestamp_format_when_setting_local_timestamps_or_precision(
+    ) -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut before = JsonEnvLogger::new();
+        before
+            .timestamp_format(TimestampFormat::Rfc2822)
+            .local_timestamps(true)
+            .timestamp_precision(chrono::SecondsFormat::Micros);
+        let mut after = JsonEnvLogger::new();
+        after
+            .local_timestamps(true)
+            .timestamp_precision(chrono::SecondsFormat::Micros)
+            .timestamp_format(TimestampFormat::Rfc2822);
+        for builder in [before, after] {
+            let parsed = write_to_json(&record, &builder.config)?;
+            assert!(
+                chrono::DateTime::parse_from_rfc2822(parsed["ts"].as_str().unwrap_or_default())
+                    .is_ok(),
+                "{}",
+                parsed["ts"]
+            );
+        }
+
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .timestamp_unit(TimestampUnit::Seconds)
+            .local_timestamps(true)
+            .clock(|| std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_594_007_117));
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert_eq!(parsed["ts"], 1_594_007_117);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "iso-timestamps")]
+    fn writes_timestamps_with_custom_format() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .timestamp_strftime("%Y/%m/%d %H:%M:%S%.3f")
+            .clock(|| std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_594_007_117_831));
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert_eq!(parsed["ts"], "2020/07/06 03:45:17.831");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "iso-timestamps")]
+    #[should_panic(expected = "invalid timestamp format")]
+    fn rejects_invalid_custom_timestamp_formats() {
+        JsonEnvLogger::new().timestamp_strftime("%Y-%!");
+    }
+
+    #[test]
+    fn writes_module_path_when_enabled() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .module_path(Some(module_path!()))
+            .build();
+        let config = Config {
+            module_path: true,
+            ..Config::default()
+        };
+        let parsed = write_to_json(&record, &config)?;
+        assert_eq!(parsed["module"], "json_env_logger::tests");
+        assert!(write_to_json(&record, &Config::default())?
+            .get("module")
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn writes_source_location_when_enabled() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .file(Some(file!()))
+            .line(Some(line!()))
+            .build();
+        let config = Config {
+            source_location: true,
+            ..Config::default()
+        };
+        let parsed = write_to_json(&record, &config)?;
+        assert_eq!(parsed["file"], file!());
+        assert!(parsed["line"].is_u64());
+        Ok(())
+    }
+
+    #[test]
+    fn writes_caller_when_enabled() -> Result<(), Box<dyn Error>> {
+        let config = Config {
+            caller: true,
+            ..Config::default()
+        };
+        let caller = |module, file, line| -> Result<serde_json::Value, Box<dyn Error>> {
+            let parsed = write_to_json(
+                &log::Record::builder()
+                    .args(format_args!("hello"))
+                    .level(log::Level::Info)
+                    .module_path(module)
+                    .file(file)
+                    .line(line)
+                    .build(),
+                &config,
+            )?;
+            Ok(parsed.get("caller").cloned().unwrap_or_default())
+        };
+        assert_eq!(
+            caller(Some("app::db"), Some("src/db.rs"), Some(42))?,
+            "app::db@src/db.rs:42"
+        );
+        assert_eq!(caller(None, Some("src/db.rs"), Some(42))?, "src/db.rs:42");
+        assert_eq!(caller(Some("app::db"), None, Some(42))?, "app::db");
+        assert_eq!(
+            caller(Some("app::db"), Some("src/db.rs"), None)?,
+            "app::db@src/db.rs"
+        );
+        assert!(caller(None, None, None)?.is_null());
+        Ok(())
+    }
+
+    #[test]
+    fn writes_target_when_enabled() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .target("http::access")
+            .build();
+        let config = Config {
+            target: true,
+            ..Config::default()
+        };
+        let parsed = write_to_json(&record, &config)?;
+        assert_eq!(parsed["target"], "http::access");
+        Ok(())
+    }
+
+    #[test]
+    fn writes_thread_name_when_enabled() -> Result<(), Box<dyn Error>> {
+        let parsed = thread::Builder::new()
+            .name("worker".into())
+            .spawn(|| {
+                let record = log::Record::builder()
+                    .args(format_args!("hello"))
+                    .level(log::Level::Info)
+                    .build();
+                let config = Config {
+                    thread_name: true,
+                    ..Config::default()
+                };
+                write_to_json(&record, &config).map_err(|e| e.to_string())
+            })?
+            .join()
+            .unwrap()?;
+        assert_eq!(parsed["thread"], "worker");
+        Ok(())
+    }
+
+    #[test]
+    fn writes_thread_id_when_enabled() -> Result<(), Box<dyn Error>> {
+        fn thread_id() -> Result<serde_json::Value, String> {
+            let record = log::Record::builder()
+                .args(format_args!("hello"))
+                .level(log::Level::Info)
+                .build();
+            let config = Config {
+                thread_id: true,
+                ..Config::default()
+            };
+            let parsed = write_to_json(&record, &config).map_err(|e| e.to_string())?;
+            Ok(parsed["thread_id"].clone())
+        }
+        let first = thread::spawn(thread_id).join().unwrap()?;
+        let second = thread::spawn(thread_id).join().unwrap()?;
+        assert!(first.is_u64());
+        assert_ne!(first, second);
+        Ok(())
+    }
+
+    #[test]
+    fn writes_sequence_numbers_when_enabled() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.with_sequence(true);
+        for expected in 0..3 {
+            assert_eq!(write_to_json(&record, &builder.config)?["seq"], expected);
+        }
+        assert!(write_to_json(&record, &Config::default())?
+            .get("seq")
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn writes_uptime_when_enabled() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.with_uptime(true);
+        let config = builder.take_config();
+        let first = write_to_json(&record, &config)?["uptime_ms"]
+            .as_u64()
+            .ok_or("expected uptime_ms")?;
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let second = write_to_json(&record, &config)?["uptime_ms"]
+            .as_u64()
+            .ok_or("expected uptime_ms")?;
+        assert!(second > first);
+        assert!(write_to_json(&record, &Config::default())?
+            .get("uptime_ms")
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn writes_logger_name_when_set() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.logger_name("my-service");
+        assert_eq!(
+            write_to_json(&record, &builder.config)?["name"],
+            "my-service"
+        );
+        assert!(write_to_json(&record, &Config::default())?
+            .get("name")
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn writes_schema_version_first_when_set() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .schema_version(2)
+            .field_order(vec![ReservedField::Message, ReservedField::Level]);
+        let mut buf = Vec::new();
+        write(&mut buf, &record, &builder.config)?;
+        assert!(std::str::from_utf8(&buf)?.starts_with(r#"{"v":2,"msg":"hello","level":"INFO""#));
+        assert!(write_to_json(&record, &Config::default())?
+            .get("v")
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn writes_source_crate_when_set() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .module_path(Some("other::module"))
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.with_source_crate(env!("CARGO_PKG_NAME"));
+        assert_eq!(
+            write_to_json(&record, &builder.config)?["crate"],
+            "json_env_logger"
+        );
+        assert!(write_to_json(&record, &Config::default())?
+            .get("crate")
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn samples_records_at_or_below_level() -> Result<(), Box<dyn Error>> {
+        let mut builder = JsonEnvLogger::new();
+        builder.sample(10, log::Level::Debug);
+        let mut buf = Vec::new();
+        for level in [log::Level::Debug, log::Level::Warn] {
+            for _ in 0..1000 {
+                write(
+                    &mut buf,
+                    &log::Record::builder()
+                        .args(format_args!("hello"))
+                        .level(level)
+                        .build(),
+                    &builder.config,
+                )?;
+            }
+        }
+        let records = std::str::from_utf8(&buf)?
+            .lines()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .collect::<Result<Vec<_>, _>>()?;
+        let (debug, warn): (Vec<_>, Vec<_>) = records
+            .iter()
+            .partition(|record| record["level"] == "DEBUG");
+        assert_eq!(debug.len(), 100);
+        assert_eq!(warn.len(), 1000);
+        assert_eq!(debug[1]["sampled_out"], 9);
+        assert!(warn[0].get("sampled_out").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn writes_pid_when_enabled() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.with_pid(true);
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert_eq!(parsed["pid"], process::id());
+        Ok(())
+    }
+
+    #[cfg(feature = "hostname")]
+    #[test]
+    fn writes_hostname() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.with_hostname(true);
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert!(parsed["host"].as_str().is_some_and(|host| !host.is_empty()));
+        Ok(())
+    }
+
+    #[test]
+    fn writes_context_fields_until_cleared() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        context::set("request_id", "stale");
+        context::set("request_id", "0b4fc3a2-7d1e-4b8a-9f6e-2c5d8a1e3f70");
+        for _ in 0..2 {
+            let parsed = write_to_json(&record, &Config::default())?;
+            assert_eq!(parsed["request_id"], "0b4fc3a2-7d1e-4b8a-9f6e-2c5d8a1e3f70");
+        }
+        let other = std::thread::spawn(|| {
+            let record = log::Record::builder()
+                .args(format_args!("hello"))
+                .level(log::Level::Info)
+                .build();
+            write_to_json(&record, &Config::default()).ok()
+        })
+        .join()
+        .unwrap()
+        .ok_or("failed to write")?;
+        assert!(other.get("request_id").is_none());
+        context::clear();
+        let parsed = write_to_json(&record, &Config::default())?;
+        assert!(parsed.get("request_id").is_none());
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn writes_context_fields_as_kv_fields() -> Result<(), Box<dyn Error>> {
+        let kvs = [("user", "bob")];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        context::set("level", "custom");
+        context::set("password", "hunter2");
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .with_timestamp(false)
+            .redact(vec!["password"])
+            .reserved_keys(ReservedKeys::Drop);
+        let mut buf = Vec::new();
+        let written = write(&mut buf, &record, &builder.config);
+        context::clear();
+        written?;
+        assert_eq!(
+            std::str::from_utf8(&buf)?.trim_end(),
+            r#"{"level":"INFO","msg":"hello","password":"[REDACTED]","user":"bob"}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn writes_static_fields_with_every_record() -> Result<(), Box<dyn Error>> {
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .with_field("service", "api")
+            .with_fields(vec![("version", "1.2.3"), ("env", "prod")]);
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        for _ in 0..2 {
+            let parsed = write_to_json(&record, &builder.config)?;
+            assert_eq!(parsed["service"], "api");
+            assert_eq!(parsed["version"], "1.2.3");
+            assert_eq!(parsed["env"], "prod");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn writes_resource_attributes_with_every_record() -> Result<(), Box<dyn Error>> {
+        let mut builder = JsonEnvLogger::new();
+        builder.with_resource(vec![
+            ("service.name", "api"),
+            ("service.version", "1.2.3"),
+            ("host.name", "web-\"1\""),
+        ]);
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        for _ in 0..2 {
+            let parsed = write_to_json(&record, &builder.config)?;
+            assert_eq!(
+                parsed["resource"],
+                serde_json::json!({
+                    "service.name": "api",
+                    "service.version": "1.2.3",
+                    "host.name": "web-\"1\"",
+                })
+            );
+        }
+        builder.resource_field("otel.resource");
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert_eq!(parsed["otel.resource"]["service.name"], "api");
+        assert!(write_to_json(&record, &Config::default())?
+            .get("resource")
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn json_format_writes_default_format() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut buf = Vec::new();
+        json_format(&mut buf, &record)?;
+        let parsed = serde_json::from_slice::<serde_json::Value>(&buf)?;
+        assert_eq!(parsed["level"], "INFO");
+        assert_eq!(parsed["msg"], "hello");
+        Ok(())
+    }
+
+    #[test]
+    fn shrinks_record_buffers_after_huge_records() -> Result<(), Box<dyn Error>> {
+        let body = "x".repeat(1024 * 1024);
+        write(
+            &mut io::sink(),
+            &log::Record::builder()
+                .args(format_args!("{}", body))
+                .level(log::Level::Info)
+                .build(),
+            &Config::default(),
+        )?;
+        assert!(BUF.with(|buf| buf.borrow().capacity()) <= MAX_RETAINED_BUF);
+        Ok(())
+    }
+
+    #[test]
+    fn json_logger_writes_enabled_records() -> Result<(), Box<dyn Error>> {
+        use log::Log;
+        let filter = env_logger::filter::Builder::new()
+            .filter_level(log::LevelFilter::Info)
+            .build();
+        let capture = Capture::default();
+        let logger = JsonLogger::with_writer(filter, capture.clone());
+        let debug = log::Record::builder()
+            .args(format_args!("hidden"))
+            .level(log::Level::Debug)
+            .build();
+        let info = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        assert!(!logger.enabled(debug.metadata()));
+        assert!(logger.enabled(info.metadata()));
+        logger.log(&debug);
+        logger.log(&info);
+        let buf = capture.0.lock().unwrap();
+        let parsed = serde_json::from_slice::<serde_json::Value>(&buf)?;
+        assert_eq!(parsed["msg"], "hello");
+        Ok(())
+    }
+
+    #[test]
+    fn builds_json_loggers_with_builder_options() -> Result<(), Box<dyn Error>> {
+        use log::Log;
+        let filter = env_logger::filter::Builder::new()
+            .filter_level(log::LevelFilter::Info)
+            .build();
+        let capture = Capture::default();
+        let logger = JsonEnvLogger::new()
+            .with_timestamp(false)
+            .message_field("message")
+            .build_json_logger(filter, capture.clone());
+        logger.log(
+            &log::Record::builder()
+                .args(format_args!("hello"))
+                .level(log::Level::Info)
+                .build(),
+        );
+        assert_eq!(
+            std::str::from_utf8(&capture.0.lock().unwrap())?,
+            "{\"level\":\"INFO\",\"message\":\"hello\"}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn writes_to_shared_writer() -> Result<(), Box<dyn Error>> {
+        use log::Log;
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let logger = JsonEnvLogger::new()
+            .filter_level(log::LevelFilter::Info)
+            .with_shared_writer(buf.clone())
+            .build();
+        logger.log(
+            &log::Record::builder()
+                .args(format_args!("hello"))
+                .level(log::Level::Info)
+                .build(),
+        );
+        let buf = buf.lock().unwrap();
+        let parsed = serde_json::from_slice::<serde_json::Value>(&buf)?;
+        assert_eq!(parsed["msg"], "hello");
+        Ok(())
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn writes_preamble_before_records() -> Result<(), Box<dyn Error>> {
+        use log::Log;
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let logger = JsonEnvLogger::new()
+            .filter_level(log::LevelFilter::Info)
+            .with_shared_writer(buf.clone())
+            .with_preamble(serde_json::json!({ "stream": "api", "version": 1 }))
+            .build();
+        for _ in 0..2 {
+            logger.log(
+                &log::Record::builder()
+                    .args(format_args!("hello"))
+                    .level(log::Level::Info)
+                    .build(),
+            );
+        }
+        let buf = buf.lock().unwrap();
+        let lines = std::str::from_utf8(&buf)?
+            .lines()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            serde_json::json!({ "stream": "api", "version": 1 })
+        );
+        assert!(lines[1..].iter().all(|line| line["msg"] == "hello"));
+        Ok(())
+    }
+
+    #[test]
+    fn flushes_buffered_writers_when_guard_is_dropped() {
+        use log::Log;
+        let capture = Capture::default();
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .parse_filters("info")
+            .with_shared_writer(Arc::new(Mutex::new(io::BufWriter::new(capture.clone()))));
+        let guard = builder.flush_guard();
+        let logger = builder.build();
+        logger.log(
+            &log::Record::builder()
+                .args(format_args!("hello"))
+                .level(log::Level::Info)
+                .build(),
+        );
+        assert!(capture.0.lock().unwrap().is_empty());
+        drop(guard);
+        assert!(!capture.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn reloads_filters_with_handle() -> Result<(), Box<dyn Error>> {
+        use log::Log;
+        let capture = Capture::default();
+        let (logger, handle) = JsonEnvLogger::new()
+            .parse_filters("info")
+            .with_shared_writer(Arc::new(Mutex::new(capture.clone())))
+            .build_reloadable();
+        let debug = || {
+            logger.log(
+                &log::Record::builder()
+                    .args(format_args!("debug"))
+                    .level(log::Level::Debug)
+                    .build(),
+            )
+        };
+        debug();
+        assert!(capture.0.lock().unwrap().is_empty());
+        assert_eq!(logger.filter(), log::LevelFilter::Info);
+
+        handle.set_filters("debug");
+        debug();
+        assert_eq!(logger.filter(), log::LevelFilter::Debug);
+        let buf = capture.0.lock().unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&buf)?["msg"],
+            "debug"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn splits_records_by_level() -> Result<(), Box<dyn Error>> {
+        use log::Log;
+        let (high, low) = (Capture::default(), Capture::default());
+        let logger = JsonEnvLogger::new()
+            .filter_level(log::LevelFilter::Info)
+            .split_by_level_with(
+                log::Level::Warn,
+                Arc::new(Mutex::new(high.clone())),
+                Arc::new(Mutex::new(low.clone())),
+            )
+            .build();
+        for (level, msg) in [(log::Level::Error, "error"), (log::Level::Info, "info")] {
+            logger.log(
+                &log::Record::builder()
+                    .args(format_args!("{}", msg))
+                    .level(level)
+                    .build(),
+            );
+        }
+        let (high, low) = (high.0.lock().unwrap(), low.0.lock().unwrap());
+        let high = serde_json::from_slice::<serde_json::Value>(&high)?;
+        let low = serde_json::from_slice::<serde_json::Value>(&low)?;
+        assert_eq!(high["msg"], "error");
+        assert_eq!(low["msg"], "info");
+        Ok(())
+    }
+
+    #[test]
+    fn writes_records_on_a_background_thread() -> Result<(), Box<dyn Error>> {
+        use log::Log;
+        let capture = Capture::default();
+        let (writer, handle) = BackgroundWriter::new(capture.clone(), 16);
+        let logger = JsonEnvLogger::new()
+            .filter_level(log::LevelFilter::Info)
+            .background(writer)
+            .build();
+        for _ in 0..3 {
+            logger.log(
+                &log::Record::builder()
+                    .args(format_args!("hello"))
+                    .level(log::Level::Info)
+                    .build(),
+            );
+        }
+        handle.shutdown()?;
+        let buf = capture.0.lock().unwrap();
+        let lines = std::str::from_utf8(&buf)?.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        for line in lines {
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(line)?["msg"],
+                "hello"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn does_no_work_for_filtered_records() {
+        use log::Log;
+        static NOW: AtomicU64 = AtomicU64::new(0);
+        let capture = Capture::default();
+        let logger = JsonEnvLogger::new()
+            .filter_level(log::LevelFilter::Info)
+            .clock(|| {
+                NOW.fetch_add(1, Ordering::SeqCst);
+                SystemTime::now()
+            })
+            .with_shared_writer(Arc::new(Mutex::new(capture.clone())))
+            .build();
+        logger.log(
+            &log::Record::builder()
+                .args(format_args!("hello"))
+                .level(log::Level::Debug)
+                .build(),
+        );
+        assert_eq!(NOW.load(Ordering::SeqCst), 0);
+        assert!(capture.0.lock().unwrap().is_empty());
+        logger.log(
+            &log::Record::builder()
+                .args(format_args!("hello"))
+                .level(log::Level::Info)
+                .build(),
+        );
+        assert_eq!(NOW.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn tees_records_to_each_writer() -> Result<(), Box<dyn Error>> {
+        use log::Log;
+        let (first, second) = (Capture::default(), Capture::default());
+        let logger = JsonEnvLogger::new()
+            .filter_level(log::LevelFilter::Info)
+            .tee(Tee::new(vec![first.clone(), second.clone()]))
+            .build();
+        logger.log(
+            &log::Record::builder()
+                .args(format_args!("hello"))
+                .level(log::Level::Info)
+                .build(),
+        );
+        let (first, second) = (first.0.lock().unwrap(), second.0.lock().unwrap());
+        assert_eq!(*first, *second);
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&first)?["msg"],
+            "hello"
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn delivers_records_to_writers_accepting_partial_writes() -> Result<(), Box<dyn Error>> {
+        /// accepts at most 3 bytes per write, interrupting every other write
+        struct Choppy {
+            capture: Capture,
+            calls: usize,
+        }
+        impl Write for Choppy {
+            fn write(
+                &mut self,
+                buf: &[u8],
+            ) -> io::Result<usize> {
+                self.calls += 1;
+                if self.calls.is_multiple_of(2) {
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                self.capture.write(&buf[..buf.len().min(3)])
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let kvs = [("user", "bob"), ("path", "/a/long/path/to/something")];
+        let record = log::Record::builder()
+            .args(format_args!("hello \"world\""))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let mut expected = Vec::new();
+        write(&mut expected, &record, &Config::default())?;
+
+        let capture = Capture::default();
+        let mut choppy = Choppy {
+            capture: capture.clone(),
+            calls: 0,
+        };
+        write(&mut choppy, &record, &Config::default())?;
+        assert!(choppy.calls > expected.len() / 3);
+
+        let teed = Capture::default();
+        let mut tee = Tee::new(vec![Choppy {
+            capture: teed.clone(),
+            calls: 0,
+        }]);
+        write(&mut tee, &record, &Config::default())?;
+
+        for captured in [capture, teed] {
+            let captured = captured.0.lock().unwrap();
+            let (captured, expected): (serde_json::Value, serde_json::Value) = (
+                serde_json::from_slice(&captured)?,
+                serde_json::from_slice(&expected)?,
+            );
+            assert_eq!(captured["msg"], expected["msg"]);
+            assert_eq!(captured["path"], expected["path"]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tee_propagates_or_ignores_errors() {
+        struct Broken;
+        impl Write for Broken {
+            fn write(
+                &mut self,
+                _: &[u8],
+            ) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let capture = Capture::default();
+        let writers =
+            || -> Vec<Box<dyn Write + Send>> { vec![Box::new(Broken), Box::new(capture.clone())] };
+        assert!(Tee::new(writers()).write_all(b"a").is_err());
+        assert!(Tee::new(writers())
+            .ignore_errors(true)
+            .write_all(b"b")
+            .is_ok());
+        assert_eq!(*capture.0.lock().unwrap(), b"ab");
+    }
+
+    #[test]
+    fn prefixes_hybrid_records_with_level_and_message() -> Result<(), Box<dyn Error>> {
+        let mut builder = JsonEnvLogger::new();
+        builder.hybrid(true);
+        let mut buf = Vec::new();
+        write(
+            &mut buf,
+            &log::Record::builder()
+                .args(format_args!("hello\nworld"))
+                .level(log::Level::Info)
+                .build(),
+            &builder.config,
+        )?;
+        let line = std::str::from_utf8(&buf)?;
+        let json = line
+            .strip_prefix("INFO hello\\nworld ")
+            .ok_or("missing prefix")?;
+        let parsed = serde_json::from_str::<serde_json::Value>(json)?;
+        assert_eq!(parsed["msg"], "hello\nworld");
+        assert_eq!(line.lines().count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn writes_hybrid_prefixes_like_json_fields() -> Result<(), Box<dyn Error>> {
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .hybrid(true)
+            .with_timestamp(false)
+            .level_lowercase()
+            .max_message_len(8)
+            .strip_ansi(true)
+            .ascii_only(true);
+        let mut buf = Vec::new();
+        write(
+            &mut buf,
+            &log::Record::builder()
+                .args(format_args!("\x1b[1mcafé\x1b[0m au lait"))
+                .level(log::Level::Info)
+                .build(),
+            &builder.config,
+        )?;
+        assert_eq!(
+            std::str::from_utf8(&buf)?,
+            "info caf\\u00e9 au\\u2026 {\"level\":\"info\",\"msg\":\"caf\\u00e9 au\\u2026\"}\n"
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn writes_pretty_json_when_enabled() -> Result<(), Box<dyn Error>> {
+        let kvs = [("path", "/a,b:{c}"), ("empty", "")];
+        let record = log::Record::builder()
+            .args(format_args!("hello \"world\""))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let config = Config {
+            pretty: true,
+            nest_fields: Some("fields".into()),
+            ..Config::default()
+        };
+        let mut buf = Vec::new();
+        write(&mut buf, &record, &config)?;
+        let output = std::str::from_utf8(&buf)?;
+        assert!(output.trim_end().lines().count() > 1);
+        assert!(output.contains("\n  \"msg\": \"hello \\\"world\\\"\""));
+        let parsed = serde_json::from_str::<serde_json::Value>(output)?;
+        let compact = write_to_json(
+            &record,
+            &Config {
+                pretty: false,
+                ..config
+            },
+        )?;
+        assert_eq!(parsed["fields"], compact["fields"]);
+        assert_eq!(parsed["msg"], compact["msg"]);
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn escapes_non_ascii_when_enabled() -> Result<(), Box<dyn Error>> {
+        let kvs = [("user", "José")];
+        let record = log::Record::builder()
+            .args(format_args!("hello 👋"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.ascii_only(true);
+        let mut buf = Vec::new();
+        write(&mut buf, &record, &builder.config)?;
+        assert!(buf.is_ascii());
+        let output = std::str::from_utf8(&buf)?;
+        assert!(output.contains(r#""msg":"hello \ud83d\udc4b""#));
+        let parsed = serde_json::from_str::<serde_json::Value>(output)?;
+        assert_eq!(parsed["msg"], "hello 👋");
+        assert_eq!(parsed["user"], "José");
+        Ok(())
+    }
+
+    #[test]
+    fn writes_configured_line_terminator() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let config = Config {
+            line_terminator: b"\r\n".to_vec(),
+            ..Config::default()
+        };
+        let mut buf = Vec::new();
+        write(&mut buf, &record, &config)?;
+        assert!(buf.ends_with(b"}\r\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn writes_configured_terminator() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        for (terminator, expected) in [
+            (Terminator::Lf, &b"}\n"[..]),
+            (Terminator::CrLf, b"}\r\n"),
+            (Terminator::None, b"\"hello\"}"),
+        ] {
+            builder.terminator(terminator);
+            let mut buf = Vec::new();
+            write(&mut buf, &record, &builder.config)?;
+            assert!(buf.ends_with(expected), "{:?}", buf);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn writes_each_record_with_a_single_write() -> Result<(), Box<dyn Error>> {
+        #[derive(Default)]
+        struct Counting {
+            writes: usize,
+            buf: Vec<u8>,
+        }
+
+        impl Write for Counting {
+            fn write(
+                &mut self,
+                buf: &[u8],
+            ) -> io::Result<usize> {
+                self.writes += 1;
+                self.buf.write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let kvs = [("user", "bob"), ("path", "/")];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let config = Config {
+            clock: Box::new(|| std::time::UNIX_EPOCH),
+            ..Config::default()
+        };
+        let mut first = Counting::default();
+        write(&mut first, &record, &config)?;
+        let mut second = Counting::default();
+        write(&mut second, &record, &config)?;
+        assert_eq!(first.writes, 1);
+        assert_eq!(first.buf, second.buf);
+        Ok(())
+    }
+
+    #[test]
+    fn escapes_format_args_like_json_strings() -> Result<(), Box<dyn Error>> {
+        let name = "\"bob\"\\\r\n\t\u{8}\u{c}\u{1}\u{1f} 👋";
+        let mut streamed = Vec::new();
+        write_json_args(&mut streamed, &format_args!("hello {}\n", name), None)?;
+        let mut allocated = Vec::new();
+        write_json_str(&mut allocated, &format!("hello {}\n", name))?;
+        assert_eq!(streamed, allocated);
+        Ok(())
+    }
+
+    #[cfg(feature = "bunyan")]
+    #[test]
+    fn writes_bunyan_records() -> Result<(), Box<dyn Error>> {
+        let mut builder = JsonEnvLogger::new();
+        builder.bunyan("my-service");
+        for (level, expected) in &[
+            (log::Level::Error, 50),
+            (log::Level::Warn, 40),
+            (log::Level::Info, 30),
+            (log::Level::Debug, 20),
+            (log::Level::Trace, 10),
+        ] {
+            let record = log::Record::builder()
+                .args(format_args!("hello"))
+                .level(*level)
+                .build();
+            let parsed = write_to_json(&record, &builder.config)?;
+            assert_eq!(parsed["level"], *expected);
+            assert_eq!(parsed["name"], "my-service");
+            assert_eq!(parsed["pid"], process::id());
+            assert_eq!(parsed["v"], 0);
+            assert_eq!(parsed["msg"], "hello");
+            assert!(parsed["hostname"].is_string());
+            assert!(parsed["time"].is_string());
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "bunyan")]
+    #[test]
+    fn writes_bunyan_fields_once() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.schema_version(1);
+        #[cfg(feature = "iso-timestamps")]
+        builder.timestamp_strftime("%Y");
+        builder.bunyan("app").bunyan("app");
+        let mut buf = Vec::new();
+        write(&mut buf, &record, &builder.config)?;
+        let output = std::str::from_utf8(&buf)?;
+        assert_eq!(output.matches("\"v\":").count(), 1, "{}", output);
+        assert_eq!(output.matches("\"hostname\":").count(), 1, "{}", output);
+        let parsed = serde_json::from_str::<serde_json::Value>(output)?;
+        assert_eq!(parsed["v"], 0);
+        assert!(
+            chrono::DateTime::parse_from_rfc3339(parsed["time"].as_str().unwrap_or_default())
+                .is_ok()
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn writes_ecs_records() -> Result<(), Box<dyn Error>> {
+        let kvs = [("user", "bob")];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.ecs();
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert!(parsed.get("@timestamp").is_some());
+        assert_eq!(parsed["log"]["level"], "INFO");
+        assert_eq!(parsed["message"], "hello");
+        assert_eq!(parsed["ecs"]["version"], ECS_VERSION);
+        assert_eq!(parsed["labels"]["user"], "bob");
+        Ok(())
+    }
+
+    #[test]
+    fn writes_gcp_records() -> Result<(), Box<dyn Error>> {
+        let mut builder = JsonEnvLogger::new();
+        builder.gcp().clock(|| {
+            std::time::UNIX_EPOCH + std::time::Duration::from_nanos(1_594_007_117_831_000_001)
+        });
+        for (level, expected) in &[
+            (log::Level::Error, "ERROR"),
+            (log::Level::Warn, "WARNING"),
+            (log::Level::Info, "INFO"),
+            (log::Level::Debug, "DEBUG"),
+            (log::Level::Trace, "DEBUG"),
+        ] {
+            let record = log::Record::builder()
+                .args(format_args!("hello"))
+                .level(*level)
+                .build();
+            let parsed = write_to_json(&record, &builder.config)?;
+            assert_eq!(parsed["severity"], *expected);
+            assert_eq!(parsed["message"], "hello");
+            assert_eq!(parsed["timestamp"]["seconds"], 1_594_007_117u64);
+            assert_eq!(parsed["timestamp"]["nanos"], 831_000_001u64);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn writes_non_finite_floats_as_null() -> Result<(), Box<dyn Error>> {
+        let kvs = [("ratio", f64::NAN), ("limit", f64::INFINITY)];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let parsed = write_to_json(&record, &Config::default())?;
+        assert!(parsed["ratio"].is_null());
+        assert!(parsed["limit"].is_null());
+        let config = Config {
+            non_finite_floats: Some("NaN".into()),
+            ..Config::default()
+        };
+        let parsed = write_to_json(&record, &config)?;
+        assert_eq!(parsed["ratio"], "NaN");
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn redacts_configured_keys() -> Result<(), Box<dyn Error>> {
+        let kvs = [("Password", "hunter2"), ("user", "bob")];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.redact(vec!["password", "token"]);
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert_eq!(parsed["Password"], REDACTED);
+        assert_eq!(parsed["user"], "bob");
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn renames_configured_keys() -> Result<(), Box<dyn Error>> {
+        let kvs = [("req.path", "/x"), ("a", "composed")];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .rename_field("req.path", "http.path")
+            .rename_field("a", "b")
+            .rename_field("b", "c");
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert_eq!(parsed["http.path"], "/x");
+        assert!(parsed.get("req.path").is_none());
+        assert_eq!(parsed["c"], "composed");
+        assert!(parsed.get("a").is_none());
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn redacts_renamed_keys() -> Result<(), Box<dyn Error>> {
+        let kvs = [("password", "hunter2"), ("token", "abc")];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .redact(vec!["password", "secret"])
+            .rename_field("password", "pw")
+            .rename_field("token", "secret");
+        let parsed = write_to_json(&record, &builder.config)?;
+        assert_eq!(parsed["pw"], REDACTED);
+        assert_eq!(parsed["secret"], REDACTED);
+        Ok(())
+    }
+
+    #[test]
+    fn strips_ansi_escape_sequences_when_enabled() -> Result<(), Box<dyn Error>> {
+        let record = log::Record::builder()
+            .args(format_args!(
+                "\x1b[1;31merror\x1b[0m: see \x1b]8;;https://example.com\x1b\\docs\x1b]8;;\x07"
+            ))
+            .level(log::Level::Info)
+            .build();
+        assert_eq!(
+            write_to_json(&record, &Config::default())?["msg"],
+            "\x1b[1;31merror\x1b[0m: see \x1b]8;;https://example.com\x1b\\docs\x1b]8;;\x07"
+        );
+        let mut builder = JsonEnvLogger::new();
+        builder.strip_ansi(true);
+        assert_eq!(
+            write_to_json(&record, &builder.config)?["msg"],
+            "error: see docs"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn truncates_long_messages() -> Result<(), Box<dyn Error>> {
+        let long = "é\"".repeat(1000);
+        let config = Config {
+            max_message_len: Some(10),
+            ..Config::default()
+        };
+        let parsed = write_to_json(
+            &log::Record::builder()
+                .args(format_args!("{}{}", long, long))
+                .level(log::Level::Info)
+                .build(),
+            &config,
+        )?;
+        assert_eq!(parsed["msg"], "é\"é\"é\"…");
+        let short = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        assert_eq!(write_to_json(&short, &config)?["msg"], "hello");
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn truncates_long_kv_string_values() -> Result<(), Box<dyn Error>> {
+        let body = "x".repeat(10_000);
+        let kvs = [
+            ("body", kv::Value::from(body.as_str())),
+            ("size", kv::Value::from(10_000)),
+            ("shown", kv::Value::from_display(&body)),
+        ];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let config = Config {
+            max_value_len: Some(4),
+            ..Config::default()
+        };
+        let parsed = write_to_json(&record, &config)?;
+        assert_eq!(parsed["body"], "xxxx…");
+        assert_eq!(parsed["size"], 10_000);
+        assert_eq!(parsed["shown"], "xxxx…");
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn logs_panic_location_with_column() -> Result<(), Box<dyn Error>> {
+        let _guard = PANIC_HOOK.lock().unwrap_or_else(|e| e.into_inner());
+        let filter = env_logger::filter::Builder::new()
+            .filter_level(log::LevelFilter::Error)
+            .build();
+        let capture = Capture::default();
+        let logger = Arc::new(JsonLogger::with_writer(filter, capture.clone()));
+        let hook_logger = logger.clone();
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            log_panic(&*hook_logger, info, &PanicHookOptions::default())
+        }));
+        let line = line!() + 1;
+        let result = panic::catch_unwind(|| panic!("boom"));
+        panic::set_hook(previous);
+        assert!(result.is_err());
+        let buf = capture.0.lock().unwrap();
+        let parsed = serde_json::from_slice::<serde_json::Value>(&buf)?;
+        assert_eq!(parsed["msg"], "panicked at 'boom'");
+        assert_eq!(parsed["line"], line);
+        assert!(parsed["column"].as_u64().unwrap_or_default() > 0);
+        assert!(parsed["location"]
+            .as_str()
+            .unwrap_or_default()
+            .starts_with(&format!("{}:{}:", file!(), line)));
+        assert!(parsed["thread"].is_string());
+        Ok(())
+    }
+
+    #[test]
+    fn escapes_multi_line_panic_messages() -> Result<(), Box<dyn Error>> {
+        let _guard = PANIC_HOOK.lock().unwrap_or_else(|e| e.into_inner());
+        let filter = env_logger::filter::Builder::new()
+            .filter_level(log::LevelFilter::Error)
+            .build();
+        let capture = Capture::default();
+        let logger = Arc::new(JsonLogger::with_writer(filter, capture.clone()));
+        let hook_logger = logger.clone();
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            log_panic(&*hook_logger, info, &PanicHookOptions::default());
+            log_panic(
+                &*hook_logger,
+                info,
+                &PanicHookOptions::new().target("panics"),
+            );
+        }));
+        let result = panic::catch_unwind(|| panic!("bad \"input\"\non line 2"));
+        panic::set_hook(previous);
+        assert!(result.is_err());
+        let buf = capture.0.lock().unwrap();
+        let lines = std::str::from_utf8(&buf)?.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let (default, targeted): (serde_json::Value, serde_json::Value) = (
+            serde_json::from_str(lines[0])?,
+            serde_json::from_str(lines[1])?,
+        );
+        assert_eq!(default["msg"], "panicked at 'bad \"input\"\non line 2'");
+        assert_eq!(targeted["msg"], "bad \"input\"\non line 2");
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn logs_panics_with_the_panicking_thread_name() -> Result<(), Box<dyn Error>> {
+        let _guard = PANIC_HOOK.lock().unwrap_or_else(|e| e.into_inner());
+        let filter = env_logger::filter::Builder::new()
+            .filter_level(log::LevelFilter::Error)
+            .build();
+        let capture = Capture::default();
+        let logger = Arc::new(JsonLogger::with_writer(filter, capture.clone()));
+        let hook_logger = logger.clone();
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            log_panic(&*hook_logger, info, &PanicHookOptions::default())
+        }));
+        let result = thread::Builder::new()
+            .name("worker-1".into())
+            .spawn(|| panic!("boom"))?
+            .join();
+        panic::set_hook(previous);
+        assert!(result.is_err());
+        let buf = capture.0.lock().unwrap();
+        let parsed = serde_json::from_slice::<serde_json::Value>(&buf)?;
+        assert_eq!(parsed["msg"], "panicked at 'boom'");
+        assert_eq!(parsed["thread"], "worker-1");
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn chained_panic_hook_invokes_previous_hook() -> Result<(), Box<dyn Error>> {
+        use std::sync::atomic::AtomicBool;
+        static SENTINEL: AtomicBool = AtomicBool::new(false);
+        static CAPTURE: OnceLock<Capture> = OnceLock::new();
+        fn logger() -> &'static dyn log::Log {
+            static LOGGER: OnceLock<JsonLogger> = OnceLock::new();
+            LOGGER.get_or_init(|| {
+                let filter = env_logger::filter::Builder::new()
+                    .filter_level(log::LevelFilter::Error)
+                    .build();
+                JsonLogger::with_writer(filter, CAPTURE.get_or_init(Capture::default).clone())
+            })
+        }
+
+        let _guard = PANIC_HOOK.lock().unwrap_or_else(|e| e.into_inner());
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(|_| SENTINEL.store(true, Ordering::SeqCst)));
+        chain_panic_hook(logger);
+        let result = panic::catch_unwind(|| panic!("boom"));
+        panic::set_hook(previous);
+        assert!(result.is_err());
+        assert!(SENTINEL.load(Ordering::SeqCst));
+        let buf = CAPTURE.get_or_init(Capture::default).0.lock().unwrap();
+        let parsed = serde_json::from_slice::<serde_json::Value>(&buf)?;
+        assert_eq!(parsed["msg"], "panicked at 'boom'");
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn logs_panics_with_configured_target() -> Result<(), Box<dyn Error>> {
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<(String, String, String)>>);
+
+        impl log::Log for Recorder {
+            fn enabled(
+                &self,
+                _: &log::Metadata,
+            ) -> bool {
+                true
+            }
+
+            fn log(
+                &self,
+                record: &log::Record,
+            ) {
+                let thread = record
+                    .key_values()
+                    .get(kv::Key::from("thread"))
+                    .map(|value| value.to_string())
+                    .unwrap_or_default();
+                self.0.lock().unwrap().push((
+                    record.target().to_owned(),
+                    record.args().to_string(),
+                    thread,
+                ));
+            }
+
+            fn flush(&self) {}
+        }
+
+        let _guard = PANIC_HOOK.lock().unwrap_or_else(|e| e.into_inner());
+        let recorder = Arc::new(Recorder::default());
+        let hook_recorder = recorder.clone();
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            log_panic(
+                &*hook_recorder,
+                info,
+                &PanicHookOptions::new().target("alerts"),
+            )
+        }));
+        let result = panic::catch_unwind(|| panic!("boom"));
+        panic::set_hook(previous);
+        assert!(result.is_err());
+        let records = recorder.0.lock().unwrap();
+        let (target, msg, thread) = &records[0];
+        assert_eq!(target, "alerts");
+        assert_eq!(msg, "boom");
+        assert!(!thread.is_empty());
+        Ok(())
+    }
+
+    #[cfg(all(feature = "backtrace", feature = "structured-values"))]
+    #[test]
+    fn logs_structured_panic_backtraces() -> Result<(), Box<dyn Error>> {
+        let _guard = PANIC_HOOK.lock().unwrap_or_else(|e| e.into_inner());
+        let filter = env_logger::filter::Builder::new()
+            .filter_level(log::LevelFilter::Error)
+            .build();
+        let capture = Capture::default();
+        let logger = Arc::new(JsonLogger::with_writer(filter, capture.clone()));
+        let hook_logger = logger.clone();
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            log_panic(
+                &*hook_logger,
+                info,
+                &PanicHookOptions::new().structured_backtrace(true),
+            )
+        }));
+        let result = panic::catch_unwind(|| panic!("boom"));
+        panic::set_hook(previous);
+        assert!(result.is_err());
+        let buf = capture.0.lock().unwrap();
+        let parsed = serde_json::from_slice::<serde_json::Value>(&buf)?;
+        let frames = parsed["backtrace"].as_array().ok_or("expected an array")?;
+        assert!(!frames.is_empty());
+        assert!(frames.iter().any(|frame| frame["symbol"].is_string()));
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn writes_error_source_chains() -> Result<(), Box<dyn Error>> {
+        #[derive(Debug)]
+        struct Outer(io::Error);
+
+        impl fmt::Display for Outer {
+            fn fmt(
+                &self,
+                f: &mut fmt::Formatter,
+            ) -> fmt::Result {
+                f.write_str("failed to load config")
+            }
+        }
+
+        impl Error for Outer {
+            fn source(&self) -> Option<&(dyn Error + 'static)> {
+                Some(&self.0)
+            }
+        }
+
+        let err = Outer(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+        let kvs = [("err", kv::Value::from_dyn_error(&err))];
+        let record = log::Record::builder()
+            .args(format_args!("failed"))
+            .level(log::Level::Error)
+            .key_values(&kvs)
+            .build();
+        let parsed = write_to_json(&record, &Config::default())?;
+        assert_eq!(parsed["err"], "failed to load config");
+        assert_eq!(
+            parsed["err_chain"],
+            serde_json::json!(["failed to load config", "no such file"])
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "tracing-span-id")]
+    #[test]
+    fn writes_current_tracing_span_id() -> Result<(), Box<dyn Error>> {
+        use tracing::{span, subscriber::Interest, Event, Metadata};
+        use tracing_core::span::Current;
+
+        /// tracks entered spans on a single thread
+        #[derive(Default)]
+        struct Spans {
+            created: Mutex<Vec<&'static Metadata<'static>>>,
+            entered: Mutex<Vec<span::Id>>,
+        }
+
+        impl tracing::Subscriber for Spans {
+            fn register_callsite(
+                &self,
+                _: &'static Metadata<'static>,
+            ) -> Interest {
+                Interest::always()
+            }
+
+            fn enabled(
+                &self,
+                _: &Metadata,
+            ) -> bool {
+                true
+            }
+
+            fn new_span(
+                &self,
+                attrs: &span::Attributes,
+            ) -> span::Id {
+                let mut created = self.created.lock().unwrap();
+                created.push(attrs.metadata());
+                span::Id::from_u64(created.len() as u64)
+            }
+
+            fn record(
+                &self,
+                _: &span::Id,
+                _: &span::Record,
+            ) {
+            }
+
+            fn record_follows_from(
+                &self,
+                _: &span::Id,
+                _: &span::Id,
+            ) {
+            }
+
+            fn event(
+                &self,
+                _: &Event,
+            ) {
+            }
+
+            fn enter(
+                &self,
+                id: &span::Id,
+            ) {
+                self.entered.lock().unwrap().push(id.clone());
+            }
+
+            fn exit(
+                &self,
+                _: &span::Id,
+            ) {
+                self.entered.lock().unwrap().pop();
+            }
+
+            fn current_span(&self) -> Current {
+                match self.entered.lock().unwrap().last() {
+                    Some(id) => {
+                        let meta = self.created.lock().unwrap()[id.into_u64() as usize - 1];
+                        Current::new(id.clone(), meta)
+                    }
+                    None => Current::none(),
+                }
+            }
+        }
+
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let config = Config {
+            span_id: true,
+            ..Config::default()
+        };
+        tracing::subscriber::with_default(Spans::default(), || {
+            let outside = write_to_json(&record, &config).map_err(|e| e.to_string())?;
+            assert!(outside.get("span_id").is_none());
+            let span = tracing::info_span!("request");
+            let _entered = span.enter();
+            let inside = write_to_json(&record, &config).map_err(|e| e.to_string())?;
+            assert_eq!(inside["span_id"], 1);
+            Ok::<_, String>(())
+        })?;
+        Ok(())
+    }
+
+    #[test]
+    fn builder_from_env_or_falls_back_on_default_filter() {
+        let var = "JSON_ENV_LOGGER_TEST_UNSET_LOG";
+        std::env::remove_var(var);
+        let logger = builder_from_env_or(var, "info").build();
+        let info = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let debug = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Debug)
+            .build();
+        assert!(logger.matches(&info));
+        assert!(!logger.matches(&debug));
+    }
+
+    #[test]
+    fn try_init_from_env_registers_logger() {
+        let var = "JSON_ENV_LOGGER_TEST_INIT_LOG";
+        std::env::set_var(var, "info");
+        assert!(try_init_from_env(var).is_ok());
+        assert!(log::logger().enabled(
+            &log::Metadata::builder()
+                .level(log::Level::Info)
+                .target("test")
+                .build()
+        ));
+        assert!(!log::logger().enabled(
+            &log::Metadata::builder()
+                .level(log::Level::Debug)
+                .target("test")
+                .build()
+        ));
+        assert!(try_init_from_env(var).is_err());
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn surfaces_write_errors_instead_of_panicking() {
+        struct Failing(usize);
+        impl Write for Failing {
+            fn write(
+                &mut self,
+                buf: &[u8],
+            ) -> io::Result<usize> {
+                if self.0 == 0 {
+                    return Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
+                }
+                let len = buf.len().min(self.0);
+                self.0 -= len;
+                Ok(len)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let kvs = [("a", "one"), ("b", "two")];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let mut object = Vec::new();
+        write_object(&mut object, &record, &Config::default()).unwrap();
+        for n in 0..object.len() {
+            let err = write_object(&mut Failing(n), &record, &Config::default()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+            let err = write(&mut Failing(n), &record, &Config::default()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        }
+    }
+
+    #[test]
+    fn autoflush_flushes_after_each_record() -> Result<(), Box<dyn Error>> {
+        #[derive(Default)]
+        struct Flushes(usize);
+        impl Write for Flushes {
+            fn write(
+                &mut self,
+                buf: &[u8],
+            ) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.0 += 1;
+                Ok(())
+            }
+        }
+
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let mut flushes = Flushes::default();
+        write(&mut flushes, &record, &Config::default())?;
+        assert_eq!(flushes.0, 0);
+
+        let mut builder = JsonEnvLogger::new();
+        builder.autoflush(true);
+        write(&mut flushes, &record, &builder.config)?;
+        write(&mut flushes, &record, &builder.config)?;
+        assert_eq!(flushes.0, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn autoflush_flushes_built_loggers_targets() {
+        use log::Log;
+        #[derive(Clone, Default)]
+        struct Flushes(Arc<Mutex<usize>>);
+        impl Write for Flushes {
+            fn write(
+                &mut self,
+                buf: &[u8],
+            ) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                *self.0.lock().unwrap() += 1;
+                Ok(())
+            }
+        }
+
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let flushes = Flushes::default();
+        let logger = JsonEnvLogger::new()
+            .parse_filters("info")
+            .with_shared_writer(Arc::new(Mutex::new(flushes.clone())))
+            .autoflush(true)
+            .build();
+        logger.log(&record);
+        logger.log(&record);
+        assert_eq!(*flushes.0.lock().unwrap(), 2);
+
+        // env_logger can't flush its target, so we write to it ourselves
+        let mut builder = JsonEnvLogger::new();
+        builder.target(env_logger::Target::Stdout);
+        builder.resolve_output();
+        assert!(builder.writer.is_none());
+        builder.autoflush(true).resolve_output();
+        assert!(builder.writer.is_some());
+    }
+
+    #[test]
+    fn buffers_records_until_flushed_or_full() {
+        use log::Log;
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .build();
+        let capture = Capture::default();
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .parse_filters("info")
+            .with_timestamp(false)
+            .with_shared_writer(Arc::new(Mutex::new(capture.clone())))
+            .buffer_capacity(64);
+        builder.resolve_output();
+        let guard = builder.flush_guard();
+        let logger = builder.build();
+        // {"level":"INFO","msg":"hello"}\n is 31 bytes
+        logger.log(&record);
+        logger.log(&record);
+        assert!(capture.0.lock().unwrap().is_empty());
+        logger.log(&record);
+        assert_eq!(capture.0.lock().unwrap().len(), 62);
+        drop(guard);
+        assert_eq!(capture.0.lock().unwrap().len(), 93);
+
+        let capture = Capture::default();
+        let logger = JsonEnvLogger::new()
+            .parse_filters("info")
+            .with_shared_writer(Arc::new(Mutex::new(capture.clone())))
+            .buffer_capacity(64)
+            .autoflush(true)
+            .build();
+        logger.log(&record);
+        assert!(!capture.0.lock().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn dedups_keys_per_strategy() -> Result<(), Box<dyn Error>> {
+        let kvs = [("a", "one"), ("b", "two"), ("a", "three")];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        for (strategy, expected) in [
+            (DedupStrategy::FirstWins, r#""a":"one","b":"two"}"#),
+            (DedupStrategy::LastWins, r#""b":"two","a":"three"}"#),
+            (
+                DedupStrategy::KeepAll,
+                r#""a":"one","b":"two","a":"three"}"#,
+            ),
+        ] {
+            let mut builder = JsonEnvLogger::new();
+            builder.with_timestamp(false).dedup_keys(strategy);
+            let mut buf = Vec::new();
+            write(&mut buf, &record, &builder.config)?;
+            let output = std::str::from_utf8(&buf)?;
+            assert!(output.ends_with(&format!("{}\n", expected)), "{}", output);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn dedups_keys_after_renaming() -> Result<(), Box<dyn Error>> {
+        let kvs = [("a", 1), ("b", 2)];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .with_timestamp(false)
+            .rename_field("b", "a")
+            .dedup_keys(DedupStrategy::LastWins);
+        let mut buf = Vec::new();
+        write(&mut buf, &record, &builder.config)?;
+        let output = std::str::from_utf8(&buf)?;
+        assert!(
+            output.ends_with("\"msg\":\"hello\",\"a\":2}\n"),
+            "{}",
+            output
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn transforms_lines_before_writing() -> Result<(), Box<dyn Error>> {
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .with_timestamp(false)
+            .transform_line(|buf| {
+                buf.pop();
+                buf.extend_from_slice(br#","sig":"abc"}"#);
+            })
+            .pretty(true);
+        let mut buf = Vec::new();
+        write(
+            &mut buf,
+            &log::Record::builder()
+                .args(format_args!("hello"))
+                .level(log::Level::Info)
+                .build(),
+            &builder.config,
+        )?;
+        let parsed: serde_json::Value = serde_json::from_slice(&buf)?;
+        assert_eq!(parsed["sig"], "abc");
+        assert_eq!(parsed["msg"], "hello");
+        assert!(buf.ends_with(b"\n}\n"));
+        Ok(())
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn transforms_json_values_before_writing() -> Result<(), Box<dyn Error>> {
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .with_timestamp(false)
+            .transform_json(|record| serde_json::json!({ "envelope": record }));
+        let mut buf = Vec::new();
+        write(
+            &mut buf,
+            &log::Record::builder()
+                .args(format_args!("hello"))
+                .level(log::Level::Info)
+                .build(),
+            &builder.config,
+        )?;
+        assert_eq!(
+            std::str::from_utf8(&buf)?,
+            "{\"envelope\":{\"level\":\"INFO\",\"msg\":\"hello\"}}\n"
+        );
+        Ok(())
+    }
+
+    #[cfg(all(feature = "kv", feature = "serde_json"))]
+    #[test]
+    fn writes_injected_fields_with_every_record() -> Result<(), Box<dyn Error>> {
+        let kvs = [("user", "bob")];
+        let mut builder = JsonEnvLogger::new();
+        builder
+            .with_timestamp(false)
+            .inject_fields(|_| vec![("request_id".into(), serde_json::json!("abc\"123"))]);
+        for _ in 0..2 {
+            let mut buf = Vec::new();
+            write(
+                &mut buf,
+                &log::Record::builder()
+                    .args(format_args!("hello"))
+                    .level(log::Level::Info)
+                    .key_values(&kvs)
+                    .build(),
+                &builder.config,
+            )?;
+            assert_eq!(
+                std::str::from_utf8(&buf)?.trim_end(),
+                r#"{"level":"INFO","msg":"hello","request_id":"abc\"123","user":"bob"}"#
+            );
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn sorts_kv_fields_when_enabled() -> Result<(), Box<dyn Error>> {
+        let kvs = [("c", 3), ("a", 1), ("b", 2)];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        let mut builder = JsonEnvLogger::new();
+        builder.with_timestamp(false).sort_fields(true);
+        let mut buf = Vec::new();
+        write(&mut buf, &record, &builder.config)?;
+        assert_eq!(
+            std::str::from_utf8(&buf)?.trim_end(),
+            r#"{"level":"INFO","msg":"hello","a":1,"b":2,"c":3}"#
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn handles_reserved_key_collisions() -> Result<(), Box<dyn Error>> {
+        let kvs = [("level", "custom"), ("other", "value")];
+        let record = log::Record::builder()
+            .args(format_args!("hello"))
+            .level(log::Level::Info)
+            .key_values(&kvs)
+            .build();
+        for (policy, expected) in [
+            (
+                ReservedKeys::Keep,
+                r#"{"level":"INFO","msg":"hello","level":"custom","other":"value"}"#,
+            ),
+            (
+                ReservedKeys::Prefix("fields.".into()),
+                r#"{"level":"INFO","msg":"hello","fields.level":"custom","other":"value"}"#,
+            ),
+            (
+                ReservedKeys::Drop,
+                r#"{"level":"INFO","msg":"hello","other":"value"}"#,
+            ),
+        ] {
+            let mut builder = JsonEnvLogger::new();
+            builder.with_timestamp(false).reserved_keys(policy);
+            let mut buf = Vec::new();
+            write(&mut buf, &record, &builder.config)?;
+            assert_eq!(std::str::from_utf8(&buf)?.trim_end(), expected);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn handles_message_key_collisions() -> Result<(), Box<dyn Error>> {
+        let kvs = [("msg", "other")];
+        for (policy, message, expected) in [
+            (
+                ReservedKeys::Keep,
+                "text",
+                r#"{"level":"INFO","msg":"text","msg":"other"}"#,
+            ),
+            (
+                ReservedKeys::Prefix("fields.".into()),
+                "text",
+                r#"{"level":"INFO","msg":"text","fields.msg":"other"}"#,
+            ),
+            (
+                ReservedKeys::Drop,
+                "text",
+                r#"{"level":"INFO","msg":"text"}"#,
+            ),
+            // an omitted message leaves nothing to collide with
+            (ReservedKeys::Drop, "", r#"{"level":"INFO","msg":"other"}"#),
+        ] {
+            let mut builder = JsonEnvLogger::new();
+            builder
+                .with_timestamp(false)
+                .omit_empty_message(true)
+                .reserved_keys(policy);
+            let mut buf = Vec::new();
+            write(
+                &mut buf,
+                &log::Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(log::Level::Info)
+                    .key_values(&kvs)
+                    .build(),
+                &builder.config,
+            )?;
+            assert_eq!(std::str::from_utf8(&buf)?.trim_end(), expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn escapes_json_strings() -> Result<(), Box<dyn Error>> {
+        let mut buf = Vec::new();
+        write_json_str(
+            &mut buf, r#""
+	"#,
+        )?;
+        assert_eq!("\"\\\"\\n\\t\"", std::str::from_utf8(&buf)?);
+        Ok(())
+    }
+
+    #[cfg(feature = "kv")]
+    #[cfg(not(feature = "serde_json"))]
+    #[test]
+    fn writes_floats_as_serde_json_does() -> Result<(), Box<dyn Error>> {
+        for value in [
+            0.0,
+            -0.0,
+            1.0,
+            0.1,
+            -2.5,
+            1e-7,
+            -1.5e-7,
+            1e-5,
+            1.234e-5,
+            1e-4,
+            12345.678,
+            1e15,
+            1e16,
+            1.5e16,
+            1e21,
+            5e-324,
+            f64::MAX,
+            f64::MIN_POSITIVE,
+        ] {
+            let mut buf = Vec::new();
+            write_shortest_float(&mut buf, value)?;
+            assert_eq!(std::str::from_utf8(&buf)?, serde_json::to_string(&value)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn escapes_json_control_characters() -> Result<(), Box<dyn Error>> {
+        let mut buf = Vec::new();
+        write_json_str(&mut buf, "\"\\\n\t\r\u{8}\u{c}\u{0}\u{1f}\u{7f}é")?;
+        assert_eq!(
+            "\"\\\"\\\\\\n\\t\\r\\b\\f\\u0000\\u001f\u{7f}é\"",
+            std::str::from_utf8(&buf)?
+        );
         Ok(())
     }
 }
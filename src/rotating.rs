@@ -0,0 +1,232 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// A file writer which rolls the file it writes to over once it grows past a size
+/// or age, renaming the previous file with a numeric suffix, i.e. `app.log.1`
+///
+/// Each write is kept to a single file so records are never split across files
+///
+/// ```rust,no_run
+/// use json_env_logger::RotatingFileTarget;
+///
+/// let target = RotatingFileTarget::new("app.log")
+///     .unwrap()
+///     .max_bytes(10 * 1024 * 1024);
+/// json_env_logger::builder().rotating_file(target).init();
+/// ```
+pub struct RotatingFileTarget {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    opened: SystemTime,
+    rotations: u64,
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+    #[cfg(feature = "gzip")]
+    compress: bool,
+}
+
+impl RotatingFileTarget {
+    /// Opens the provided path for appending, creating it if it doesn't exist
+    pub fn new(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let file = open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(RotatingFileTarget {
+            path,
+            file,
+            written,
+            opened: SystemTime::now(),
+            rotations: 0,
+            max_bytes: None,
+            max_age: None,
+            #[cfg(feature = "gzip")]
+            compress: false,
+        })
+    }
+
+    /// Rolls the file over before a write would grow it past the provided number of bytes
+    pub fn max_bytes(
+        mut self,
+        bytes: u64,
+    ) -> Self {
+        self.max_bytes = Some(bytes);
+        self
+    }
+
+    /// Rolls the file over before writing once it has been open for the provided duration
+    pub fn max_age(
+        mut self,
+        age: Duration,
+    ) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Enables or disables gzip compressing rolled over files, i.e. `app.log.1.gz`.
+    /// Defaults to `false`
+    #[cfg(feature = "gzip")]
+    pub fn compress(
+        mut self,
+        compress: bool,
+    ) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    fn should_rotate(
+        &self,
+        len: usize,
+    ) -> bool {
+        if self.written == 0 {
+            return false;
+        }
+        let oversized = self
+            .max_bytes
+            .is_some_and(|max| self.written + len as u64 > max);
+        let expired = self
+            .max_age
+            .is_some_and(|max| self.opened.elapsed().is_ok_and(|elapsed| elapsed >= max));
+        oversized || expired
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let rolled = loop {
+            self.rotations += 1;
+            let mut rolled = self.path.clone().into_os_string();
+            rolled.push(format!(".{}", self.rotations));
+            let rolled = PathBuf::from(rolled);
+            if !rolled.exists() && !gz_path(&rolled).exists() {
+                break rolled;
+            }
+        };
+        fs::rename(&self.path, &rolled)?;
+        self.file = open(&self.path)?;
+        self.written = 0;
+        self.opened = SystemTime::now();
+        #[cfg(feature = "gzip")]
+        {
+            if self.compress {
+                let raw = fs::read(&rolled)?;
+                fs::write(gz_path(&rolled), gzip(&raw))?;
+                fs::remove_file(&rolled)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for RotatingFileTarget {
+    fn write(
+        &mut self,
+        buf: &[u8],
+    ) -> io::Result<usize> {
+        if self.should_rotate(buf.len()) {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn gz_path(path: &Path) -> PathBuf {
+    let mut gz = path.to_owned().into_os_string();
+    gz.push(".gz");
+    PathBuf::from(gz)
+}
+
+/// Encodes bytes as a single member gzip file (RFC 1952)
+#[cfg(feature = "gzip")]
+fn gzip(raw: &[u8]) -> Vec<u8> {
+    // magic, deflate, no flags, no mtime, no extra flags, unknown os
+    let mut gz = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    gz.extend(miniz_oxide::deflate::compress_to_vec(raw, 6));
+    gz.extend(crc32(raw).to_le_bytes());
+    gz.extend((raw.len() as u32).to_le_bytes());
+    gz
+}
+
+#[cfg(feature = "gzip")]
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    fn temp_dir(name: &str) -> io::Result<PathBuf> {
+        let dir =
+            std::env::temp_dir().join(format!("json_env_logger_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn rotates_files_past_max_bytes() -> Result<(), Box<dyn Error>> {
+        let dir = temp_dir("rotates")?;
+        let path = dir.join("app.log");
+        let mut target = RotatingFileTarget::new(&path)?.max_bytes(100);
+        for _ in 0..10 {
+            target.write_all(b"{\"level\":\"INFO\",\"msg\":\"hello\"}\n")?;
+        }
+        target.flush()?;
+        assert!(dir.join("app.log.1").exists());
+        for entry in fs::read_dir(&dir)? {
+            let contents = fs::read_to_string(entry?.path())?;
+            assert!(contents.len() <= 100);
+            assert!(contents
+                .lines()
+                .all(|line| line == "{\"level\":\"INFO\",\"msg\":\"hello\"}"));
+        }
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compresses_rolled_files() -> Result<(), Box<dyn Error>> {
+        let dir = temp_dir("compresses")?;
+        let path = dir.join("app.log");
+        let mut target = RotatingFileTarget::new(&path)?.max_bytes(10).compress(true);
+        target.write_all(b"first\n")?;
+        target.write_all(b"second\n")?;
+        let gz = fs::read(dir.join("app.log.1.gz"))?;
+        assert_eq!(&gz[..2], &[0x1f, 0x8b]);
+        assert_eq!(
+            &gz[gz.len() - 8..gz.len() - 4],
+            &crc32(b"first\n").to_le_bytes()
+        );
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert!(!dir.join("app.log.1").exists());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}